@@ -0,0 +1,414 @@
+//! 吞吐与延迟基准：进程内启动服务端和本地 sink，通过它跑大量并发的 CONNECT 会话和 UDP 关联
+//!
+//! ```bash
+//! cargo bench --bench throughput
+//! cargo bench --bench throughput -- --mode copy --concurrency 128 --megabytes 512
+//! ```
+//!
+//! TCP 部分对 splice 和 `copy_bidirectional` 两条转发路径各跑一遍，结果可直接对比
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use clap::{Parser, ValueEnum};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+use proxy::Address;
+use proxy::Server;
+use proxy::client::Client;
+
+const CHUNK: usize = 64 * 1024;
+const SESSION_TIMEOUT: Duration = Duration::from_secs(120);
+const UDP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// sink 的会话类型，由 CONNECT 之后的第一个字节决定
+const SINK_UPLOAD: u8 = b'u';
+const SINK_DOWNLOAD: u8 = b'd';
+
+#[derive(Parser, Debug)]
+#[command(about = "SOCKS5 throughput and latency benchmark")]
+struct Args {
+    /// 要测的 TCP 转发路径
+    #[arg(long, value_enum, default_value = "both")]
+    mode: Mode,
+
+    /// 建连测试的并发数
+    #[arg(long, default_value_t = 64)]
+    concurrency: usize,
+
+    /// 建连测试的时长 (秒)
+    #[arg(long, default_value_t = 5)]
+    duration: u64,
+
+    /// 吞吐测试的并发会话数
+    #[arg(long, default_value_t = 8)]
+    sessions: usize,
+
+    /// 吞吐测试中每个会话每个方向传输的数据量 (MiB)
+    #[arg(long, default_value_t = 256)]
+    megabytes: usize,
+
+    /// 并发的 UDP 关联数
+    #[arg(long, default_value_t = 32)]
+    udp_associations: usize,
+
+    /// 每个 UDP 关联往返的数据报数
+    #[arg(long, default_value_t = 1000)]
+    udp_packets: usize,
+
+    /// 数据报负载大小 (字节)
+    #[arg(long, default_value_t = 512)]
+    udp_size: usize,
+
+    /// 开启用户名/密码认证 (握手延迟包含 RFC 1929 子协商)
+    #[arg(long)]
+    auth: bool,
+
+    /// `cargo bench` 传给所有基准的参数
+    #[arg(long, hide = true)]
+    bench: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Mode {
+    Splice,
+    Copy,
+    Both,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let tcp_sink = tcp_sink().await?;
+    let udp_sink = udp_sink().await?;
+
+    let modes: &[bool] = match args.mode {
+        Mode::Splice => &[true],
+        Mode::Copy => &[false],
+        Mode::Both => &[true, false],
+    };
+    if !cfg!(target_os = "linux") && args.mode != Mode::Copy {
+        println!("splice is Linux only; the splice runs fall back to copy_bidirectional");
+    }
+
+    for &splice in modes {
+        let proxy = start(&args, splice).await?;
+        let client = client(&args, proxy);
+        println!(
+            "== TCP via {} ==",
+            if splice {
+                "splice"
+            } else {
+                "copy_bidirectional"
+            }
+        );
+        bench_connect(&args, &client, tcp_sink).await;
+        bench_transfer(&args, &client, tcp_sink, SINK_UPLOAD).await;
+        bench_transfer(&args, &client, tcp_sink, SINK_DOWNLOAD).await;
+    }
+
+    let proxy = start(&args, true).await?;
+    println!("== UDP ASSOCIATE ==");
+    bench_udp(&args, &client(&args, proxy), udp_sink).await;
+    Ok(())
+}
+
+/// 在临时端口上启动服务端
+async fn start(args: &Args, splice: bool) -> Result<SocketAddr, Box<dyn Error>> {
+    let mut builder = Server::builder().splice(splice);
+    if args.auth {
+        builder = builder.user("bench", "bench");
+    }
+    let server = builder.build().await?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move { server.serve(listener).await.map_err(|e| e.to_string()) });
+    Ok(addr)
+}
+
+fn client(args: &Args, proxy: SocketAddr) -> Client {
+    let client = Client::new(proxy.to_string());
+    if args.auth {
+        client.auth("bench", "bench")
+    } else {
+        client
+    }
+}
+
+/// TCP sink：上传会话读到 EOF 后回报收到的字节数；下载会话先读 8 字节长度，再发送这么多数据；
+/// 没有数据的连接 (建连测试) 直接关闭
+async fn tcp_sink() -> Result<SocketAddr, Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let _ = sink_session(socket).await;
+            });
+        }
+    });
+    Ok(addr)
+}
+
+async fn sink_session(mut socket: TcpStream) -> std::io::Result<()> {
+    let mut kind = [0u8; 1];
+    if socket.read(&mut kind).await? == 0 {
+        return Ok(());
+    }
+    let mut buf = vec![0u8; CHUNK];
+    match kind[0] {
+        SINK_UPLOAD => {
+            let mut total = 0u64;
+            loop {
+                let n = socket.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                total += n as u64;
+            }
+            socket.write_all(&total.to_be_bytes()).await?;
+        }
+        SINK_DOWNLOAD => {
+            let mut len = [0u8; 8];
+            socket.read_exact(&mut len).await?;
+            let mut remaining = u64::from_be_bytes(len) as usize;
+            while remaining > 0 {
+                let n = remaining.min(CHUNK);
+                socket.write_all(&buf[..n]).await?;
+                remaining -= n;
+            }
+        }
+        _ => {}
+    }
+    socket.shutdown().await
+}
+
+/// UDP 回显
+async fn udp_sink() -> Result<SocketAddr, Box<dyn Error>> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let addr = socket.local_addr()?;
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+            let _ = socket.send_to(&buf[..len], src).await;
+        }
+    });
+    Ok(addr)
+}
+
+/// 建连速率和握手延迟：从 TCP 连接到收到 CONNECT 成功应答
+async fn bench_connect(args: &Args, client: &Client, sink: SocketAddr) {
+    let deadline = Instant::now() + Duration::from_secs(args.duration);
+    let started = Instant::now();
+    let mut workers = Vec::with_capacity(args.concurrency);
+    for _ in 0..args.concurrency {
+        let client = client.clone();
+        workers.push(tokio::spawn(async move {
+            let mut latencies = Vec::new();
+            let mut failed = 0usize;
+            while Instant::now() < deadline {
+                let begin = Instant::now();
+                match client.connect(Address::from(sink.ip()), sink.port()).await {
+                    Ok(_) => latencies.push(begin.elapsed()),
+                    Err(_) => failed += 1,
+                }
+            }
+            (latencies, failed)
+        }));
+    }
+
+    let mut latencies = Vec::new();
+    let mut failed = 0;
+    for worker in workers {
+        if let Ok((l, f)) = worker.await {
+            latencies.extend(l);
+            failed += f;
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    println!(
+        "connect   {:>8} ok {:>6} failed {:>10.0} conn/s   handshake {}",
+        latencies.len(),
+        failed,
+        latencies.len() as f64 / elapsed,
+        percentiles(&mut latencies)
+    );
+}
+
+/// 吞吐：`sessions` 个会话同时上传或下载 `megabytes` MiB
+async fn bench_transfer(args: &Args, client: &Client, sink: SocketAddr, kind: u8) {
+    let bytes = (args.megabytes * 1024 * 1024) as u64;
+    let started = Instant::now();
+    let mut sessions = Vec::with_capacity(args.sessions);
+    for _ in 0..args.sessions {
+        let client = client.clone();
+        sessions.push(tokio::spawn(async move {
+            timeout(
+                SESSION_TIMEOUT,
+                transfer_session(&client, sink, kind, bytes),
+            )
+            .await
+            .map_err(|_| "timed out".to_string())
+            .and_then(|r| r)
+        }));
+    }
+
+    let mut total = 0u64;
+    let mut errors = Vec::new();
+    for session in sessions {
+        match session.await {
+            Ok(Ok(n)) => total += n,
+            Ok(Err(e)) => errors.push(e),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    let name = if kind == SINK_UPLOAD {
+        "upload"
+    } else {
+        "download"
+    };
+    println!(
+        "{:<9} {:>3} x {} MiB {:>6} failed {:>10.2} Gbit/s   in {:.2}s",
+        name,
+        args.sessions,
+        args.megabytes,
+        errors.len(),
+        total as f64 * 8.0 / elapsed / 1e9,
+        elapsed
+    );
+    if let Some(e) = errors.first() {
+        println!("          first error: {}", e);
+    }
+}
+
+/// 一个吞吐会话，返回实际完成传输的字节数
+async fn transfer_session(
+    client: &Client,
+    sink: SocketAddr,
+    kind: u8,
+    bytes: u64,
+) -> Result<u64, String> {
+    let (mut stream, _) = client
+        .connect(Address::from(sink.ip()), sink.port())
+        .await
+        .map_err(|e| e.to_string())?;
+    let io = |e: std::io::Error| e.to_string();
+    stream.write_all(&[kind]).await.map_err(io)?;
+
+    if kind == SINK_UPLOAD {
+        let buf = vec![0u8; CHUNK];
+        let mut remaining = bytes as usize;
+        while remaining > 0 {
+            let n = remaining.min(CHUNK);
+            stream.write_all(&buf[..n]).await.map_err(io)?;
+            remaining -= n;
+        }
+        stream.shutdown().await.map_err(io)?;
+        let mut received = [0u8; 8];
+        stream.read_exact(&mut received).await.map_err(io)?;
+        let received = u64::from_be_bytes(received);
+        if received != bytes {
+            return Err(format!("sink received {} of {} bytes", received, bytes));
+        }
+        Ok(received)
+    } else {
+        stream.write_all(&bytes.to_be_bytes()).await.map_err(io)?;
+        let mut buf = vec![0u8; CHUNK];
+        let mut received = 0u64;
+        loop {
+            let n = stream.read(&mut buf).await.map_err(io)?;
+            if n == 0 {
+                break;
+            }
+            received += n as u64;
+        }
+        if received != bytes {
+            return Err(format!("received {} of {} bytes", received, bytes));
+        }
+        Ok(received)
+    }
+}
+
+/// UDP：关联建立延迟、数据报往返延迟和速率
+async fn bench_udp(args: &Args, client: &Client, sink: SocketAddr) {
+    let started = Instant::now();
+    let mut associations = Vec::with_capacity(args.udp_associations);
+    for _ in 0..args.udp_associations {
+        let client = client.clone();
+        let (packets, size) = (args.udp_packets, args.udp_size);
+        associations.push(tokio::spawn(async move {
+            let begin = Instant::now();
+            let association = client.udp_associate().await.map_err(|e| e.to_string())?;
+            let setup = begin.elapsed();
+
+            let payload = vec![0x5a; size];
+            let mut buf = vec![0u8; size + 64];
+            let mut rtts = Vec::with_capacity(packets);
+            let mut lost = 0usize;
+            for _ in 0..packets {
+                let sent = Instant::now();
+                association
+                    .send_to(&payload, Address::from(sink.ip()), sink.port())
+                    .await
+                    .map_err(|e| e.to_string())?;
+                match timeout(UDP_TIMEOUT, association.recv_from(&mut buf)).await {
+                    Ok(Ok(_)) => rtts.push(sent.elapsed()),
+                    _ => lost += 1,
+                }
+            }
+            Ok::<_, String>((setup, rtts, lost))
+        }));
+    }
+
+    let mut setups = Vec::new();
+    let mut rtts = Vec::new();
+    let mut lost = 0;
+    let mut failed = 0;
+    for association in associations {
+        match association.await {
+            Ok(Ok((setup, r, l))) => {
+                setups.push(setup);
+                rtts.extend(r);
+                lost += l;
+            }
+            _ => failed += 1,
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    println!(
+        "associate {:>8} ok {:>6} failed   setup {}",
+        setups.len(),
+        failed,
+        percentiles(&mut setups)
+    );
+    println!(
+        "datagrams {:>8} ok {:>6} lost {:>10.0} round trips/s   rtt {}",
+        rtts.len(),
+        lost,
+        rtts.len() as f64 / elapsed,
+        percentiles(&mut rtts)
+    );
+}
+
+fn percentiles(samples: &mut [Duration]) -> String {
+    if samples.is_empty() {
+        return "-".to_string();
+    }
+    samples.sort();
+    let at = |p: f64| {
+        let index = ((samples.len() as f64 * p).ceil() as usize).clamp(1, samples.len()) - 1;
+        samples[index].as_secs_f64() * 1e3
+    };
+    format!(
+        "p50 {:.3}ms p90 {:.3}ms p99 {:.3}ms max {:.3}ms",
+        at(0.50),
+        at(0.90),
+        at(0.99),
+        at(1.0)
+    )
+}
@@ -0,0 +1,40 @@
+#![no_main]
+
+use std::sync::OnceLock;
+
+use libfuzzer_sys::fuzz_target;
+use proxy::Server;
+use proxy::handler::handshake;
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Runtime;
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    })
+}
+
+fn server() -> &'static Server {
+    static SERVER: OnceLock<Server> = OnceLock::new();
+    SERVER.get_or_init(|| {
+        runtime()
+            .block_on(Server::builder().user("admin", "secret123").build())
+            .unwrap()
+    })
+}
+
+// 客户端的整个字节流 (协商、认证、请求) 经内存 duplex 交给服务端的 handshake；
+// 写完后关闭客户端的写方向，服务端读到 EOF 时结束，应答留在 duplex 的缓冲区里
+fuzz_target!(|data: &[u8]| {
+    let config = server().config();
+    runtime().block_on(async {
+        let (mut client, mut socket) = tokio::io::duplex(data.len() + 4096);
+        client.write_all(data).await.unwrap();
+        client.shutdown().await.unwrap();
+        let _ = handshake(&mut socket, config).await;
+    });
+});
@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use proxy::auth::{User, perform_password_auth};
+use proxy::config::UdpOverride;
+
+// RFC 1929 子协商：VER ULEN UNAME PLEN PASSWD
+fuzz_target!(|data: &[u8]| {
+    let users = [User {
+        username: "admin".into(),
+        password: "secret123".into(),
+        udp: UdpOverride::default(),
+    }];
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let mut stream = tokio::io::join(data, tokio::io::sink());
+    if let Ok(user) = runtime.block_on(perform_password_auth(&mut stream, &users)) {
+        assert_eq!(user.username, "admin");
+        assert!(data.starts_with(b"\x01\x05admin\x09secret123"));
+    }
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use proxy::SocksRequest;
+
+// 请求头：VER CMD RSV ATYP DST.ADDR DST.PORT
+fuzz_target!(|data: &[u8]| {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let mut reader = data;
+    if let Ok(request) = runtime.block_on(SocksRequest::read_from(&mut reader)) {
+        // 解析成功的请求重新编码后应与读掉的字节一致 (RSV 不校验，编码为 0)
+        let mut consumed = data[..data.len() - reader.len()].to_vec();
+        consumed[2] = 0x00;
+        let mut buf = Vec::new();
+        request.write(&mut buf);
+        assert_eq!(buf, consumed);
+    }
+});
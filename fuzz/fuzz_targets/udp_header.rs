@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use proxy::UDPAssociateHeader;
+
+// UDP 中继数据报头部：RSV FRAG ATYP DST.ADDR DST.PORT
+fuzz_target!(|data: &[u8]| {
+    if let Ok((header, consumed)) = UDPAssociateHeader::parse(data) {
+        let mut buf = Vec::new();
+        header.write(&mut buf);
+        assert_eq!(buf, &data[..consumed]);
+    }
+});
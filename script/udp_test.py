@@ -9,6 +9,8 @@ PROXY_PORT = 8080 # 对应你 Rust 代码里的端口
 USERNAME = 'admin'
 PASSWORD = '123'
 ENABLE_AUTH = True # 如果你的 Rust 代码开启了 force auth，设为 True
+# 客户端网络屏蔽 UDP 时设为 True：使用扩展命令 0x83，数据报成帧后走 TCP 控制连接
+UDP_OVER_TCP = False
 
 def test_udp():
     # 1. 建立 TCP 控制连接
@@ -36,6 +38,10 @@ def test_udp():
     
     print("[TCP] Handshake & Auth success")
 
+    if UDP_OVER_TCP:
+        test_udp_over_tcp(tcp_sock)
+        return
+
     # 3. 请求 UDP Associate
     # Ver 5, Cmd 3 (UDP), Rsv 0, Atyp 1 (IPv4), 0.0.0.0:0
     # 注意：客户端告诉代理 "我想发 UDP"，后面的 IP:Port 通常填 0，由代理决定分配什么
@@ -110,5 +116,40 @@ def test_udp():
         tcp_sock.close()
         udp_sock.close()
 
+def test_udp_over_tcp(tcp_sock):
+    # Ver 5, Cmd 0x83 (UDP over TCP), Rsv 0, Atyp 1 (IPv4), 0.0.0.0:0
+    tcp_sock.sendall(b'\x05\x83\x00\x01\x00\x00\x00\x00\x00\x00')
+    resp = tcp_sock.recv(10)
+    if resp[1] != 0x00:
+        print(f"[TCP] UDP over TCP request failed with REP: {resp[1]}")
+        return
+
+    dns_query = b'\xAA\xAA\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00' \
+                b'\x06google\x03com\x00\x00\x01\x00\x01'
+    header = b'\x00\x00\x00\x01' + socket.inet_aton('8.8.8.8') + struct.pack('!H', 53)
+    packet = header + dns_query
+
+    # 帧格式: LEN(2) | SOCKS5 UDP 报文
+    print(f"[TCP] Sending framed DNS query via proxy to 8.8.8.8:53...")
+    tcp_sock.sendall(struct.pack('!H', len(packet)) + packet)
+
+    tcp_sock.settimeout(5)
+    try:
+        (length,) = struct.unpack('!H', tcp_sock.recv(2))
+        data = b''
+        while len(data) < length:
+            data += tcp_sock.recv(length - len(data))
+        real_payload = data[10:] if data[3] == 1 else data
+
+        print(f"[TCP] Received {len(data)} bytes frame from proxy")
+        if b'google' in real_payload:
+            print("\n✅ 测试成功！成功通过 UDP over TCP 收到了 DNS 响应。")
+        else:
+            print("\n❓ 收到数据，但看起来不像 DNS 响应。")
+    except socket.timeout:
+        print("\n❌ 测试失败：接收超时")
+    finally:
+        tcp_sock.close()
+
 if __name__ == '__main__':
     test_udp()
\ No newline at end of file
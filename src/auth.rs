@@ -1,28 +1,59 @@
-// src/auth.rs
+use crate::config::{TimeoutConfig, UdpConfig, UdpOverride};
 use crate::consts::*;
-use std::error::Error;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use crate::dns::Dns;
+use crate::error::SocksError;
+use crate::limits::Associations;
+use crate::ports::UdpPorts;
+use crate::proxy_protocol::ProxyProtocol;
+use crate::reverse::Registry;
+use crate::rules::Rules;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tracing::{debug, info, warn};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct User {
     pub username: String,
     pub password: String,
+    /// 该用户 UDP 限制的覆盖
+    #[serde(default)]
+    pub udp: UdpOverride,
 }
 // 简单的用户配置结构
 #[derive(Debug, Clone)]
 pub struct UserConfig {
-    pub user: Option<User>,
+    pub users: Vec<User>,
     pub timeout: u8,
+    pub splice: bool,            // TCP 转发是否走 splice 零拷贝 (仅 Linux)
+    pub timeouts: TimeoutConfig, // 客户端协商各阶段和转发空闲的超时
+    pub udp: UdpConfig,
+    pub associations: Arc<Associations>, // 所有监听共享的 UDP 关联计数
+    pub udp_ports: Arc<UdpPorts>,        // 所有监听共享的 UDP 端口分配
+    pub rules: Arc<Rules>,
+    pub dns: Arc<Dns>,                              // 所有监听共享的解析器和缓存
+    pub reverse: Arc<Registry>,                     // 已注册的反向隧道代理端
+    pub proxy_protocol: Option<Arc<ProxyProtocol>>, // 所有监听共用的可信 PROXY protocol 来源
+}
+
+impl UserConfig {
+    /// 某个用户实际生效的 UDP 配置
+    pub fn udp_for(&self, user: Option<&User>) -> UdpConfig {
+        match user {
+            Some(user) => self.udp.with_override(&user.udp),
+            None => self.udp.clone(),
+        }
+    }
 }
 
-pub async fn perform_password_auth(
-    socket: &mut TcpStream,
-    user_config: &User,
-) -> Result<(), Box<dyn Error>> {
+/// 返回认证通过的用户
+pub async fn perform_password_auth<'a, S>(
+    socket: &mut S,
+    users: &'a [User],
+) -> Result<&'a User, SocksError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     // 1. 读取版本号和用户名长度 [VER, ULEN]
     let mut header = [0u8; 2];
     socket.read_exact(&mut header).await?;
@@ -31,7 +62,7 @@ pub async fn perform_password_auth(
     let ulen = header[1] as usize;
 
     if ver != AUTH_VERSION {
-        return Err(format!("unsupport auth version: {}", ver).into());
+        return Err(SocksError::UnsupportedVersion(ver));
     }
 
     // 2. 读取用户名
@@ -52,13 +83,16 @@ pub async fn perform_password_auth(
     debug!("[Auth] 尝试认证: {} / ***", username);
 
     // 5. 校验
-    if username == user_config.username && password == user_config.password {
+    if let Some(user) = users
+        .iter()
+        .find(|u| u.username == username && u.password == password)
+    {
         socket.write_all(&[AUTH_VERSION, AUTH_SUCCESS]).await?;
         info!("用户 {} 认证成功", username);
-        Ok(())
+        Ok(user)
     } else {
         socket.write_all(&[AUTH_VERSION, AUTH_FAILURE]).await?;
         warn!("用户 {} 认证失败: 密码错误", username);
-        Err("身份验证失败".into())
+        Err(SocksError::AuthFailed(username))
     }
 }
@@ -0,0 +1,437 @@
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Mutex;
+
+use crate::consts::*;
+
+/// 一次收发的最大报文数
+pub const BATCH: usize = 16;
+/// 缓冲池中每块缓冲的大小，能容纳任意 UDP 报文 (或 GRO 合并后的报文)
+const SLOT_SIZE: usize = MAX_UDP_SIZE as usize;
+/// 缓冲池最多保留的空闲缓冲块数
+const POOL_CAPACITY: usize = 128;
+
+/// 所有关联共享的接收缓冲池
+///
+/// 接收时才从池中取缓冲，处理完归还，空闲的关联不占用缓冲
+static POOL: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+fn take_buffers(n: usize) -> Vec<Vec<u8>> {
+    let mut pool = POOL.lock().unwrap();
+    let reuse = pool.len().min(n);
+    let start = pool.len() - reuse;
+    let mut buffers: Vec<_> = pool.drain(start..).collect();
+    drop(pool);
+    buffers.resize_with(n, || vec![0u8; SLOT_SIZE]);
+    buffers
+}
+
+fn put_buffers(buffers: &mut Vec<Vec<u8>>) {
+    let mut pool = POOL.lock().unwrap();
+    for buf in buffers.drain(..) {
+        if pool.len() >= POOL_CAPACITY {
+            break;
+        }
+        if buf.len() == SLOT_SIZE {
+            pool.push(buf);
+        }
+    }
+}
+
+/// 一次接收到的若干报文，drop 时把缓冲还给缓冲池
+pub struct RecvBatch {
+    buffers: Vec<Vec<u8>>,
+    datagrams: Vec<(usize, Range<usize>, SocketAddr)>, // 缓冲下标、报文在缓冲中的范围、来源
+}
+
+impl RecvBatch {
+    fn with_buffers(n: usize) -> Self {
+        RecvBatch {
+            buffers: take_buffers(n),
+            datagrams: Vec::with_capacity(n),
+        }
+    }
+
+    /// 再从缓冲池取 `n` 块缓冲，返回新缓冲的起始下标
+    #[cfg(target_os = "linux")]
+    fn grow(&mut self, n: usize) -> usize {
+        let start = self.buffers.len();
+        self.buffers.extend(take_buffers(n));
+        start
+    }
+
+    /// 由已经收好的报文组成 (如共享端口分发来的报文)
+    pub fn from_packets(packets: Vec<(Vec<u8>, SocketAddr)>) -> Self {
+        let mut batch = RecvBatch {
+            buffers: Vec::with_capacity(packets.len()),
+            datagrams: Vec::with_capacity(packets.len()),
+        };
+        for (packet, src) in packets {
+            batch
+                .datagrams
+                .push((batch.buffers.len(), 0..packet.len(), src));
+            batch.buffers.push(packet);
+        }
+        batch
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+        self.datagrams
+            .iter()
+            .map(|(i, range, src)| (&self.buffers[*i][range.clone()], *src))
+    }
+}
+
+impl Drop for RecvBatch {
+    fn drop(&mut self) {
+        put_buffers(&mut self.buffers);
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::{enable_gro, from_socket_addr, recv, send, to_socket_addr};
+
+#[cfg(not(target_os = "linux"))]
+pub use fallback::{enable_gro, recv, send};
+
+/// Linux：recvmmsg / sendmmsg 批量收发，UDP_GRO 合并接收，UDP_SEGMENT (GSO) 合并发送
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+    use std::os::fd::{AsRawFd, RawFd};
+    use std::ptr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::io::Interest;
+    use tokio::net::UdpSocket;
+    use tracing::{debug, warn};
+
+    use super::{BATCH, RecvBatch};
+
+    // libc 只在部分 target env 下导出这两个常量
+    const UDP_SEGMENT: libc::c_int = 103;
+    const UDP_GRO: libc::c_int = 104;
+    /// GSO 单次最多合并的分段数 (内核 UDP_MAX_SEGMENTS)
+    const GSO_MAX_SEGMENTS: usize = 64;
+    /// 只合并不超过该大小的分段，避免超过出口 MTU 时整批被拒绝
+    const GSO_MAX_SEGMENT_SIZE: usize = 1200;
+    /// GSO 合并后的总长度上限
+    const GSO_MAX_BYTES: usize = 65000;
+
+    /// 发送失败过一次后不再使用 GSO
+    static GSO_SUPPORTED: AtomicBool = AtomicBool::new(true);
+
+    /// 足够放下一个 int 的控制消息，按 cmsghdr 对齐
+    type Control = [u64; 8];
+
+    /// 开启 UDP_GRO，内核不支持时忽略；开启后该 socket 只能用 [`recv`] 接收
+    pub fn enable_gro(socket: &UdpSocket) {
+        let on: libc::c_int = 1;
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_UDP,
+                UDP_GRO,
+                &on as *const _ as *const libc::c_void,
+                mem::size_of_val(&on) as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            debug!("UDP_GRO unavailable: {}", io::Error::last_os_error());
+        }
+    }
+
+    /// 等待可读后用 recvmmsg 取出最多 BATCH 个报文，GRO 合并的报文按分段大小拆开
+    ///
+    /// 缓冲按需取用：先收 1 个报文，每次取满就把下一次的数量翻倍，
+    /// 只有一两个报文排队的 socket 不会一次占用 BATCH 块 64 KiB 的缓冲
+    pub async fn recv(socket: &UdpSocket) -> io::Result<RecvBatch> {
+        loop {
+            socket.readable().await?;
+            let mut batch = RecvBatch::with_buffers(0);
+            let mut want = 1;
+            loop {
+                let start = batch.grow(want);
+                match socket.try_io(Interest::READABLE, || {
+                    recvmmsg(socket.as_raw_fd(), &mut batch, start)
+                }) {
+                    Ok(received) if received == want && batch.buffers.len() < BATCH => {
+                        want = (want * 2).min(BATCH - batch.buffers.len());
+                    }
+                    Ok(_) => return Ok(batch),
+                    // 已经收到的报文先交出去，错误留给下一次调用
+                    Err(_) if !batch.datagrams.is_empty() => return Ok(batch),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    /// 收到 `batch.buffers[start..]` 中，返回收到的报文数 (含被丢弃的截断报文)
+    fn recvmmsg(fd: RawFd, batch: &mut RecvBatch, start: usize) -> io::Result<usize> {
+        let n = batch.buffers.len() - start;
+        let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; n];
+        let mut controls: Vec<Control> = vec![[0; 8]; n];
+        let mut iovs: Vec<libc::iovec> = batch.buffers[start..]
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr().cast(),
+                iov_len: buf.len(),
+            })
+            .collect();
+        let mut hdrs: Vec<libc::mmsghdr> = (0..n)
+            .map(|i| {
+                let mut hdr: libc::mmsghdr = unsafe { mem::zeroed() };
+                hdr.msg_hdr.msg_name = (&mut addrs[i] as *mut libc::sockaddr_storage).cast();
+                hdr.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+                hdr.msg_hdr.msg_iov = &mut iovs[i];
+                hdr.msg_hdr.msg_iovlen = 1;
+                hdr.msg_hdr.msg_control = controls[i].as_mut_ptr().cast();
+                hdr.msg_hdr.msg_controllen = mem::size_of::<Control>() as _;
+                hdr
+            })
+            .collect();
+
+        let ret = unsafe {
+            libc::recvmmsg(
+                fd,
+                hdrs.as_mut_ptr(),
+                n as libc::c_uint,
+                libc::MSG_DONTWAIT,
+                ptr::null_mut(),
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        for (i, hdr) in hdrs.iter().enumerate().take(ret as usize) {
+            let len = hdr.msg_len as usize;
+            if hdr.msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+                debug!("drop truncated udp datagram");
+                continue;
+            }
+            let Some(src) = to_socket_addr(&addrs[i]) else {
+                continue;
+            };
+            let segment = gro_segment(&hdr.msg_hdr).unwrap_or(len).max(1);
+            let mut offset = 0;
+            while offset < len {
+                let end = (offset + segment).min(len);
+                batch.datagrams.push((start + i, offset..end, src));
+                offset = end;
+            }
+        }
+        Ok(ret as usize)
+    }
+
+    fn gro_segment(hdr: &libc::msghdr) -> Option<usize> {
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(hdr);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == UDP_GRO {
+                    let segment = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                    return Some(segment as usize);
+                }
+                cmsg = libc::CMSG_NXTHDR(hdr, cmsg);
+            }
+        }
+        None
+    }
+
+    /// 把发往同一地址的若干报文用 sendmmsg 发出，连续的等长报文用 GSO 合并成一个
+    pub async fn send(
+        socket: &UdpSocket,
+        target: SocketAddr,
+        packets: &[Vec<u8>],
+    ) -> io::Result<()> {
+        let mut sent = 0;
+        while sent < packets.len() {
+            socket.writable().await?;
+            let gso = GSO_SUPPORTED.load(Ordering::Relaxed);
+            match socket.try_io(Interest::WRITABLE, || {
+                sendmmsg(socket.as_raw_fd(), target, &packets[sent..], gso)
+            }) {
+                Ok(n) => sent += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e)
+                    if gso
+                        && matches!(
+                            e.raw_os_error(),
+                            Some(libc::EINVAL | libc::EIO | libc::ENOPROTOOPT)
+                        ) =>
+                {
+                    warn!("UDP GSO send failed ({}), disabling GSO", e);
+                    GSO_SUPPORTED.store(false, Ordering::Relaxed);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// 返回实际发出的报文数
+    fn sendmmsg(
+        fd: RawFd,
+        target: SocketAddr,
+        packets: &[Vec<u8>],
+        gso: bool,
+    ) -> io::Result<usize> {
+        // 分组：每组是连续的等长报文 (最后一个可以更短)，组内用 GSO 一次发出
+        let mut groups: Vec<Group> = Vec::with_capacity(packets.len());
+        let mut start = 0;
+        while start < packets.len() {
+            let segment = packets[start].len();
+            let mut end = start + 1;
+            let mut total = segment;
+            if gso && segment <= GSO_MAX_SEGMENT_SIZE {
+                while end < packets.len()
+                    && end - start < GSO_MAX_SEGMENTS
+                    && total + packets[end].len() <= GSO_MAX_BYTES
+                    && packets[end].len() <= segment
+                {
+                    total += packets[end].len();
+                    end += 1;
+                    if packets[end - 1].len() < segment {
+                        break;
+                    }
+                }
+            }
+            groups.push(Group {
+                start,
+                end,
+                segment,
+            });
+            start = end;
+        }
+
+        let (mut addr, addr_len) = from_socket_addr(target);
+        let mut iovs: Vec<libc::iovec> = packets
+            .iter()
+            .map(|packet| libc::iovec {
+                iov_base: packet.as_ptr() as *mut libc::c_void,
+                iov_len: packet.len(),
+            })
+            .collect();
+        let mut controls: Vec<Control> = vec![[0; 8]; groups.len()];
+        let mut hdrs: Vec<libc::mmsghdr> = Vec::with_capacity(groups.len());
+        for (group, control) in groups.iter().zip(controls.iter_mut()) {
+            let mut hdr: libc::mmsghdr = unsafe { mem::zeroed() };
+            hdr.msg_hdr.msg_name = (&mut addr as *mut libc::sockaddr_storage).cast();
+            hdr.msg_hdr.msg_namelen = addr_len;
+            hdr.msg_hdr.msg_iov = &mut iovs[group.start];
+            hdr.msg_hdr.msg_iovlen = (group.end - group.start) as _;
+            if group.end - group.start > 1 {
+                unsafe { set_segment(&mut hdr.msg_hdr, control, group.segment as u16) };
+            }
+            hdrs.push(hdr);
+        }
+
+        let ret = unsafe { libc::sendmmsg(fd, hdrs.as_mut_ptr(), hdrs.len() as libc::c_uint, 0) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(groups
+            .iter()
+            .take(ret as usize)
+            .map(|group| group.end - group.start)
+            .sum())
+    }
+
+    /// 一组用同一个 msghdr 发出的报文 packets[start..end]
+    struct Group {
+        start: usize,
+        end: usize,
+        segment: usize,
+    }
+
+    unsafe fn set_segment(hdr: &mut libc::msghdr, control: &mut Control, segment: u16) {
+        unsafe {
+            let space = libc::CMSG_SPACE(mem::size_of::<u16>() as u32) as usize;
+            hdr.msg_control = control.as_mut_ptr().cast();
+            hdr.msg_controllen = space as _;
+            let cmsg = libc::CMSG_FIRSTHDR(hdr);
+            (*cmsg).cmsg_level = libc::SOL_UDP;
+            (*cmsg).cmsg_type = UDP_SEGMENT;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment);
+        }
+    }
+
+    pub fn to_socket_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let addr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                    u16::from_be(addr.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                let addr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                    u16::from_be(addr.sin6_port),
+                    addr.sin6_flowinfo,
+                    addr.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn from_socket_addr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let len = match addr {
+            SocketAddr::V4(addr) => {
+                let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr) => {
+                let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_addr.s6_addr = addr.ip().octets();
+                sin6.sin6_flowinfo = addr.flowinfo();
+                sin6.sin6_scope_id = addr.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (storage, len as libc::socklen_t)
+    }
+}
+
+/// 其他平台：逐个收发
+#[cfg(not(target_os = "linux"))]
+mod fallback {
+    use std::io;
+    use std::net::SocketAddr;
+    use tokio::net::UdpSocket;
+
+    use super::RecvBatch;
+
+    pub fn enable_gro(_socket: &UdpSocket) {}
+
+    pub async fn recv(socket: &UdpSocket) -> io::Result<RecvBatch> {
+        let mut batch = RecvBatch::with_buffers(1);
+        let (len, src) = socket.recv_from(&mut batch.buffers[0]).await?;
+        batch.datagrams.push((0, 0..len, src));
+        Ok(batch)
+    }
+
+    pub async fn send(
+        socket: &UdpSocket,
+        target: SocketAddr,
+        packets: &[Vec<u8>],
+    ) -> io::Result<()> {
+        for packet in packets {
+            socket.send_to(packet, target).await?;
+        }
+        Ok(())
+    }
+}
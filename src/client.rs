@@ -0,0 +1,261 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::debug;
+
+use crate::consts::*;
+use crate::error::SocksError;
+use crate::protocol::{Address, SocksRequest, UDPAssociateHeader};
+
+/// SOCKS5 客户端，每个请求新建一条到 `server` (`host:port`) 的连接
+///
+/// ```no_run
+/// # async fn run() -> Result<(), proxy::SocksError> {
+/// use proxy::{Address, client::Client};
+///
+/// let client = Client::new("127.0.0.1:1080").auth("admin", "secret123");
+/// let (stream, bound) = client.connect(Address::Domain("example.com".into()), 80).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Client {
+    server: String,
+    auth: Option<(String, String)>,
+}
+
+/// BND.ADDR / BND.PORT
+pub type Bound = (Address, u16);
+
+impl Client {
+    pub fn new(server: impl Into<String>) -> Self {
+        Client {
+            server: server.into(),
+            auth: None,
+        }
+    }
+
+    /// 使用 RFC 1929 用户名/密码认证
+    pub fn auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.auth = Some((username.into(), password.into()));
+        self
+    }
+
+    /// 连上代理并完成协商 (和认证)
+    async fn open(&self) -> Result<TcpStream, SocksError> {
+        let mut stream = TcpStream::connect(&self.server)
+            .await
+            .map_err(|e| SocksError::connect(&self.server, e))?;
+        stream.set_nodelay(true)?;
+        let auth = self.auth.as_ref().map(|(u, p)| (u.as_str(), p.as_str()));
+        negotiate(&mut stream, auth).await?;
+        Ok(stream)
+    }
+
+    /// CONNECT：返回已连上目标的流和代理出站使用的地址
+    pub async fn connect(
+        &self,
+        address: Address,
+        port: u16,
+    ) -> Result<(TcpStream, Bound), SocksError> {
+        let mut stream = self.open().await?;
+        let bound = request(&mut stream, &new_request(CMD_CONNECT, address, port)).await?;
+        Ok((stream, bound))
+    }
+
+    /// BIND：代理开始监听，返回其监听地址；目标连入后由 [`Bind::accept`] 取得连接
+    pub async fn bind(&self, address: Address, port: u16) -> Result<Bind, SocksError> {
+        let mut stream = self.open().await?;
+        let bound = request(&mut stream, &new_request(CMD_BIND, address, port)).await?;
+        Ok(Bind { stream, bound })
+    }
+
+    /// UDP ASSOCIATE：在本地绑定一个 UDP socket，经代理的中继端口收发数据报
+    pub async fn udp_associate(&self) -> Result<UdpAssociation, SocksError> {
+        let mut control = self.open().await?;
+        let proxy_ip = control.peer_addr()?.ip();
+        let unspecified = match proxy_ip {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let (address, port) = request(
+            &mut control,
+            &new_request(CMD_UDP_ASSOCIATE, Address::from(unspecified), 0),
+        )
+        .await?;
+
+        // BND.ADDR 为全 0 时使用控制连接的服务器地址
+        let relay = match address.to_socket_addr(port) {
+            Some(addr) if !addr.ip().is_unspecified() => addr,
+            _ => SocketAddr::new(proxy_ip, port),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?;
+        debug!("UDP associate relay {}", relay);
+        Ok(UdpAssociation {
+            _control: control,
+            socket,
+            relay,
+        })
+    }
+
+    /// RESOLVE 扩展命令：由代理解析域名
+    pub async fn resolve(&self, name: &str) -> Result<Address, SocksError> {
+        let mut stream = self.open().await?;
+        let resolve = new_request(CMD_RESOLVE, Address::Domain(name.to_string()), 0);
+        Ok(request(&mut stream, &resolve).await?.0)
+    }
+
+    /// RESOLVE_PTR 扩展命令：由代理反向解析 IP
+    pub async fn resolve_ptr(&self, ip: IpAddr) -> Result<Address, SocksError> {
+        let mut stream = self.open().await?;
+        let resolve = new_request(CMD_RESOLVE_PTR, Address::from(ip), 0);
+        Ok(request(&mut stream, &resolve).await?.0)
+    }
+}
+
+/// 进行中的 BIND 请求
+#[derive(Debug)]
+pub struct Bind {
+    stream: TcpStream,
+    bound: Bound,
+}
+
+impl Bind {
+    /// 代理的监听地址，需要告诉目标连入这里
+    pub fn bound(&self) -> &Bound {
+        &self.bound
+    }
+
+    /// 等待第二个应答 (目标已连入)，返回连接和目标的地址
+    pub async fn accept(mut self) -> Result<(TcpStream, Bound), SocksError> {
+        let peer = reply(&mut self.stream, "BIND").await?;
+        Ok((self.stream, peer))
+    }
+}
+
+/// UDP 关联，控制连接关闭 (drop) 时代理结束关联
+#[derive(Debug)]
+pub struct UdpAssociation {
+    _control: TcpStream,
+    socket: UdpSocket,
+    relay: SocketAddr,
+}
+
+impl UdpAssociation {
+    /// 代理的 UDP 中继地址
+    pub fn relay(&self) -> SocketAddr {
+        self.relay
+    }
+
+    pub async fn send_to(
+        &self,
+        data: &[u8],
+        address: Address,
+        port: u16,
+    ) -> Result<(), SocksError> {
+        let header = UDPAssociateHeader {
+            frag: 0,
+            address,
+            port,
+        };
+        let mut packet = Vec::with_capacity(data.len() + 22);
+        header.write(&mut packet);
+        packet.extend_from_slice(data);
+        self.socket.send_to(&packet, self.relay).await?;
+        Ok(())
+    }
+
+    /// 接收一个数据报，负载写入 `buf`，返回长度和来源；分片和非中继来源的报文被丢弃
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, Bound), SocksError> {
+        let mut packet = vec![0u8; MAX_UDP_SIZE as usize];
+        loop {
+            let (len, src) = self.socket.recv_from(&mut packet).await?;
+            if src != self.relay {
+                continue;
+            }
+            let (header, header_len) = UDPAssociateHeader::parse(&packet[..len])?;
+            if header.frag != 0 {
+                continue;
+            }
+            let payload = &packet[header_len..len];
+            let n = payload.len().min(buf.len());
+            buf[..n].copy_from_slice(&payload[..n]);
+            return Ok((n, (header.address, header.port)));
+        }
+    }
+}
+
+fn new_request(cmd: u8, address: Address, port: u16) -> SocksRequest {
+    SocksRequest { cmd, address, port }
+}
+
+/// 方法协商，需要时完成 RFC 1929 用户名/密码认证
+pub async fn negotiate<S>(stream: &mut S, auth: Option<(&str, &str)>) -> Result<(), SocksError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let method = match auth {
+        Some(_) => METHOD_PASSWORD,
+        None => METHOD_NO_AUTH,
+    };
+    stream.write_all(&[SOCKS_VERSION, 1, method]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(SocksError::UnsupportedVersion(reply[0]));
+    }
+    if reply[1] != method {
+        return Err(SocksError::NoAcceptableMethod);
+    }
+
+    if let Some((user, pass)) = auth {
+        if user.len() > 255 || pass.len() > 255 {
+            return Err(SocksError::Protocol("credentials too long".into()));
+        }
+        let mut buf = vec![AUTH_VERSION, user.len() as u8];
+        buf.extend_from_slice(user.as_bytes());
+        buf.push(pass.len() as u8);
+        buf.extend_from_slice(pass.as_bytes());
+        stream.write_all(&buf).await?;
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != AUTH_SUCCESS {
+            return Err(SocksError::AuthFailed(user.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// 发送请求并读取应答，失败的应答码转换为 [`SocksError::Upstream`]；返回 BND.ADDR / BND.PORT
+pub async fn request<S>(stream: &mut S, request: &SocksRequest) -> Result<Bound, SocksError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = Vec::new();
+    request.write(&mut buf);
+    stream.write_all(&buf).await?;
+    let what = format!("{} {}", command_name(request.cmd), request);
+    reply(stream, &what).await
+}
+
+/// 读取一个应答：格式与请求相同，CMD 的位置是 REP
+async fn reply<S>(stream: &mut S, what: &str) -> Result<Bound, SocksError>
+where
+    S: AsyncRead + Unpin,
+{
+    let reply = SocksRequest::read_from(stream).await?;
+    if reply.cmd != REP_SUCCESS {
+        return Err(SocksError::Upstream(what.to_string(), reply.cmd));
+    }
+    Ok((reply.address, reply.port))
+}
+
+fn command_name(cmd: u8) -> &'static str {
+    match cmd {
+        CMD_CONNECT => "CONNECT",
+        CMD_BIND => "BIND",
+        CMD_UDP_ASSOCIATE => "UDP ASSOCIATE",
+        CMD_RESOLVE => "RESOLVE",
+        CMD_RESOLVE_PTR => "RESOLVE_PTR",
+        _ => "request",
+    }
+}
@@ -0,0 +1,334 @@
+use serde::Deserialize;
+use std::error::Error;
+use std::path::Path;
+
+use crate::auth::User;
+use crate::consts::{HANDSHAKE_TIMEOUT, MAX_UDP_SIZE, UDP_TIMEOUT};
+
+/// TOML 配置文件
+///
+/// 所有字段均可省略，命令行参数优先级高于配置文件
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub ip: Option<String>,
+    pub port: Option<u16>,
+    pub timeout: Option<u8>,
+    /// TCP 转发是否使用 splice 零拷贝 (仅 Linux)，默认开启；关闭后使用用户态拷贝
+    pub splice: Option<bool>,
+    pub users: Vec<User>,
+    pub websocket: Option<WebSocketConfig>,
+    pub tunnel: Option<TunnelConfig>,
+    pub local: Option<LocalConfig>,
+    pub agent: Option<AgentConfig>,
+    pub transparent: Option<TransparentConfig>,
+    pub forward: Vec<ForwardConfig>,
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
+    pub udp: UdpConfig,
+    pub rules: RulesConfig,
+    pub dns: DnsConfig,
+    pub timeouts: TimeoutConfig,
+}
+
+/// WebSocket 监听配置 (服务端)
+///
+/// 在 `listen` 上接受路径为 `path` 的 WebSocket 升级，之后在消息流上跑普通的 SOCKS5 流程
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebSocketConfig {
+    pub listen: String,
+    #[serde(default = "default_ws_path")]
+    pub path: String,
+    /// 该监听上 UDP 限制的覆盖
+    #[serde(default)]
+    pub udp: UdpOverride,
+}
+
+/// 加密隧道监听配置 (远端节点)
+///
+/// 接受本地节点用 `key` 加密的连接，替它们完成 CONNECT / UDP ASSOCIATE 出站
+#[derive(Debug, Clone, Deserialize)]
+pub struct TunnelConfig {
+    pub listen: String,
+    pub key: String,
+    /// 该监听上 UDP 限制的覆盖
+    #[serde(default)]
+    pub udp: UdpOverride,
+}
+
+/// 本地客户端模式配置
+///
+/// 在 ip:port 暴露普通的 SOCKS5 端口，把每个会话转发到 `server`：
+/// - `ws://` / `wss://` 地址：经 WebSocket 原样转发，由服务端完成 SOCKS5 流程
+/// - `host:port` 地址：本地完成协商后经加密隧道交给远端节点，需要配置 `key`
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocalConfig {
+    pub server: String,
+    pub key: Option<String>,
+}
+
+impl LocalConfig {
+    pub fn is_websocket(&self) -> bool {
+        self.server.starts_with("ws://") || self.server.starts_with("wss://")
+    }
+}
+
+/// 反向隧道代理端配置
+///
+/// 主动连接服务端的加密隧道监听 (`server`，使用其 `key`) 并注册 `services`，
+/// 之后 SOCKS5 客户端 CONNECT `<name>.tunnel` 即经本端连接对应的 `target`
+#[derive(Debug, Clone, Deserialize)]
+pub struct AgentConfig {
+    pub server: String,
+    pub key: String,
+    pub services: Vec<ServiceConfig>,
+}
+
+/// 代理端暴露的服务
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceConfig {
+    pub name: String,
+    /// `host:port`，CONNECT 请求中的端口会被忽略
+    pub target: String,
+}
+
+/// 接受 PROXY protocol (v1 / v2) 头部
+///
+/// 来自 `trusted` (IP 或 CIDR) 的连接必须先发送头部，其中的客户端地址
+/// 用于日志、限制和 UDP 客户端地址；其他来源仍按普通客户端处理
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProxyProtocolConfig {
+    pub trusted: Vec<String>,
+}
+
+/// 透明代理监听配置 (Linux)
+///
+/// 接受 iptables 重定向来的连接，不走 SOCKS5 协商，直接按原始目标出站
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransparentConfig {
+    pub listen: String,
+    #[serde(default)]
+    pub mode: TransparentMode,
+    /// 同时在 `listen` 上接收 TPROXY 转来的 UDP (只支持 tproxy 模式)
+    #[serde(default)]
+    pub udp: bool,
+}
+
+/// 透明代理取原始目标的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransparentMode {
+    /// iptables REDIRECT：从 conntrack 读取 SO_ORIGINAL_DST
+    #[default]
+    Redirect,
+    /// iptables TPROXY：socket 的本地地址就是原始目标，需要 CAP_NET_ADMIN
+    Tproxy,
+}
+
+/// 静态端口转发
+///
+/// 在 `listen` 上接受连接 (或 UDP 报文)，全部转发到固定的 `target`；
+/// TCP 转发可以经 `via` 中的上游 SOCKS5 代理依次出站
+#[derive(Debug, Clone, Deserialize)]
+pub struct ForwardConfig {
+    pub listen: String,
+    /// `host:port`
+    pub target: String,
+    #[serde(default)]
+    pub protocol: ForwardProtocol,
+    /// 上游代理链 (`socks5://[user:pass@]host:port`)，按顺序逐跳连接
+    #[serde(default)]
+    pub via: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardProtocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+/// UDP ASSOCIATE 配置
+///
+/// 数量和速率限制为 0 时表示不限制
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UdpConfig {
+    /// 目标回包的过滤策略
+    pub filter: UdpFilter,
+    /// 单个目标的 NAT 会话空闲多久后关闭 (秒)
+    pub session_timeout: u64,
+    /// 整个 UDP 关联空闲多久后关闭 (秒)，只有客户端的报文和目标的应答会刷新
+    pub idle_timeout: u64,
+    /// 全局同时存在的 UDP 关联数上限
+    pub max_associations: usize,
+    /// 每个用户 (无认证时为每个客户端 IP) 同时存在的 UDP 关联数上限
+    pub max_associations_per_user: usize,
+    /// 单个 UDP 关联的目标数上限
+    pub max_destinations: usize,
+    /// 单个 UDP 关联每个方向每秒转发的数据报数上限
+    pub rate_limit: u32,
+    /// 客户端单个数据报负载的最大字节数
+    pub max_datagram_size: usize,
+    /// 面向客户端的 UDP 端口范围 `[最小, 最大]`，不设置时使用随机端口
+    pub port_range: Option<(u16, u16)>,
+    /// 所有关联共用的 UDP 端口，按客户端地址分发报文，优先于 `port_range`
+    pub shared_port: Option<u16>,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        UdpConfig {
+            filter: UdpFilter::default(),
+            session_timeout: 120,
+            idle_timeout: UDP_TIMEOUT as u64,
+            max_associations: 0,
+            max_associations_per_user: 0,
+            max_destinations: 0,
+            rate_limit: 0,
+            max_datagram_size: MAX_UDP_SIZE as usize,
+            port_range: None,
+            shared_port: None,
+        }
+    }
+}
+
+impl UdpConfig {
+    /// 应用监听或用户级别的覆盖
+    pub fn with_override(&self, o: &UdpOverride) -> UdpConfig {
+        UdpConfig {
+            idle_timeout: o.idle_timeout.unwrap_or(self.idle_timeout),
+            max_associations_per_user: o
+                .max_associations_per_user
+                .unwrap_or(self.max_associations_per_user),
+            max_destinations: o.max_destinations.unwrap_or(self.max_destinations),
+            rate_limit: o.rate_limit.unwrap_or(self.rate_limit),
+            ..self.clone()
+        }
+    }
+}
+
+/// 监听 (`[websocket]`、`[tunnel]`) 或用户 (`[[users]]`) 对 `[udp]` 中同名限制的覆盖
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct UdpOverride {
+    pub idle_timeout: Option<u64>,
+    pub max_associations_per_user: Option<usize>,
+    pub max_destinations: Option<usize>,
+    pub rate_limit: Option<u32>,
+}
+
+/// 出站 socket 上哪些回包可以送回客户端
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UdpFilter {
+    /// 任何来源都可以
+    FullCone,
+    /// 来源 IP 必须是客户端发过包的目标 IP，端口不限
+    AddressRestricted,
+    /// 来源必须正好是客户端发过包的目标 IP:端口
+    #[default]
+    PortRestricted,
+}
+
+/// 客户端连接各阶段的超时 (秒)，0 表示不限制
+///
+/// 出站连接的超时是顶层的 `timeout`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// 连上之后发来方法协商 (greeting) 的时限
+    pub greeting: u64,
+    /// 用户名/密码子协商的时限
+    pub auth: u64,
+    /// 协商完成后发来请求的时限
+    pub request: u64,
+    /// TCP 转发两个方向都没有数据多久后关闭，默认不限制
+    pub idle: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            greeting: HANDSHAKE_TIMEOUT,
+            auth: HANDSHAKE_TIMEOUT,
+            request: HANDSHAKE_TIMEOUT,
+            idle: 0,
+        }
+    }
+}
+
+/// 访问规则
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RulesConfig {
+    /// 禁止访问的域名 (含子域名)，对 CONNECT、UDP 目标和 DNS 查询生效
+    pub block: Vec<String>,
+    /// CONNECT 到这些目标时，先在出站连接上写入 PROXY protocol 头部
+    pub proxy_protocol: Vec<ProxyHeaderRule>,
+}
+
+/// 目标匹配 `domains` (含子域名) 或其 IP 落在 `networks` (IP 或 CIDR) 中时发送 `version` 版本的头部
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProxyHeaderRule {
+    #[serde(default)]
+    pub domains: Vec<String>,
+    #[serde(default)]
+    pub networks: Vec<String>,
+    #[serde(default = "default_proxy_header_version")]
+    pub version: u8,
+}
+
+fn default_proxy_header_version() -> u8 {
+    2
+}
+
+/// DNS 配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    /// 拦截 UDP 关联中发往 53 端口的查询，由代理应答
+    pub intercept: bool,
+    /// 默认上游 (`ip` 或 `ip:port`)，不设置时拦截的查询转发给客户端原本查询的服务器，
+    /// 其他域名解析使用系统解析器
+    pub upstream: Option<String>,
+    /// 按域名选择上游
+    pub routes: Vec<DnsRoute>,
+    /// 缓存的应答数上限，0 表示不缓存
+    pub cache_size: usize,
+    /// 缓存时间上限 (秒)
+    pub max_ttl: u64,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        DnsConfig {
+            intercept: false,
+            upstream: None,
+            routes: Vec::new(),
+            cache_size: 4096,
+            max_ttl: 3600,
+        }
+    }
+}
+
+/// 匹配 `domains` (含子域名) 的查询发往 `upstream`
+#[derive(Debug, Clone, Deserialize)]
+pub struct DnsRoute {
+    pub domains: Vec<String>,
+    pub upstream: String,
+}
+
+pub fn default_ws_path() -> String {
+    "/".to_string()
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("read config {}: {}", path.display(), e))?;
+        let config = toml::from_str(&content)
+            .map_err(|e| format!("parse config {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+}
@@ -20,7 +20,6 @@ pub const SOCKS_VERSION: u8 = 0x05;
 
 // auth methods
 pub const METHOD_NO_AUTH: u8 = 0x00;
-pub const METHOD_GASSAPI: u8 = 0x01;
 pub const METHOD_PASSWORD: u8 = 0x02;
 pub const METHOD_NO_ACCEPTABLE: u8 = 0xFF;
 
@@ -31,6 +30,10 @@ pub const AUTH_FAILURE: u8 = 0x01;
 
 // command CMD
 pub const CMD_CONNECT: u8 = 0x01;
+pub const CMD_BIND: u8 = 0x02;
+// 扩展命令 (Tor)：只解析不连接，应答的 BND.ADDR 为解析结果
+pub const CMD_RESOLVE: u8 = 0xF0;
+pub const CMD_RESOLVE_PTR: u8 = 0xF1;
 
 // address type ATYP
 pub const ATYP_IPV4: u8 = 0x01;
@@ -50,8 +53,13 @@ pub const REP_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
 
 // UDP
 pub const CMD_UDP_ASSOCIATE: u8 = 0x03;
+// 扩展命令：UDP over TCP，数据报按 [LEN(2)][SOCKS5 UDP 报文] 成帧后走 TCP 控制连接
+pub const CMD_UDP_OVER_TCP: u8 = 0x83;
+// 扩展命令：反向隧道代理端注册名字 (DST.ADDR 为域名)，只在加密隧道上接受
+pub const CMD_REGISTER: u8 = 0x84;
 pub const RSV: u8 = 0x00;
 pub const FRAG: u8 = 0x00; // SOCKS5 分片字段，通常不实现（填0）
 
 pub const MAX_UDP_SIZE: u64 = 65535;
 pub const UDP_TIMEOUT: usize = 300;
+pub const HANDSHAKE_TIMEOUT: u64 = 10; // 协商、认证、请求各阶段的默认时限 (秒)
@@ -0,0 +1,265 @@
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::transport::Stream;
+
+// 加密隧道的线上格式 (参考 Shadowsocks AEAD):
+//
+// 握手: 客户端发送 32 字节随机 salt，服务端回复 32 字节随机 salt。
+// 每个方向的密钥 = HKDF-SHA256(PSK, salt_c || salt_s, 方向标识)，
+// 服务端 salt 每次随机，因此重放的客户端数据无法通过校验。
+//
+// 之后每个数据块:
+// +--------------+------------+--------------+------------+
+// | LEN (密文)   | LEN TAG    | DATA (密文)  | DATA TAG   |
+// +--------------+------------+--------------+------------+
+// |      2       |     16     |     LEN      |     16     |
+// +--------------+------------+--------------+------------+
+// nonce 为每个方向独立的 96 bit 小端计数器，每次加解密后加一。
+
+const SALT_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const MAX_CHUNK: usize = 0x3FFF;
+
+const INFO_C2S: &[u8] = b"proxy tunnel c2s";
+const INFO_S2C: &[u8] = b"proxy tunnel s2c";
+
+struct Cipher {
+    aead: ChaCha20Poly1305,
+    nonce: u64,
+}
+
+impl Cipher {
+    fn new(psk: &[u8], salt: &[u8], info: &[u8]) -> Self {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(salt), psk)
+            .expand(info, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Cipher {
+            aead: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            nonce: 0,
+        }
+    }
+
+    fn next_nonce(&mut self) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[..8].copy_from_slice(&self.nonce.to_le_bytes());
+        self.nonce += 1;
+        Nonce::from(nonce)
+    }
+
+    /// 原地加密，末尾追加 TAG
+    fn seal(&mut self, buf: &mut Vec<u8>) {
+        let nonce = self.next_nonce();
+        self.aead
+            .encrypt_in_place(&nonce, b"", buf)
+            .expect("Vec buffer can always grow");
+    }
+
+    /// 原地解密并校验 TAG
+    fn open(&mut self, buf: &mut Vec<u8>) -> io::Result<()> {
+        let nonce = self.next_nonce();
+        self.aead
+            .decrypt_in_place(&nonce, b"", buf)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "tunnel decrypt failed"))
+    }
+}
+
+/// AEAD 加密的字节流
+pub struct CryptoStream<S> {
+    inner: S,
+    enc: Cipher,
+    dec: Cipher,
+    // 写方向: 已加密待发送的数据
+    wbuf: Vec<u8>,
+    wpos: usize,
+    // 读方向: 已收到未解密的密文、当前块长度、已解密待读取的明文
+    rbuf: Vec<u8>,
+    chunk_len: Option<usize>,
+    plain: Vec<u8>,
+    plain_pos: usize,
+}
+
+/// 客户端握手
+pub async fn connect<S>(mut inner: S, psk: &[u8]) -> io::Result<CryptoStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut salt_c = [0u8; SALT_LEN];
+    rand::rng().fill_bytes(&mut salt_c);
+    inner.write_all(&salt_c).await?;
+
+    let mut salt_s = [0u8; SALT_LEN];
+    inner.read_exact(&mut salt_s).await?;
+
+    let salt = [salt_c, salt_s].concat();
+    Ok(CryptoStream::new(
+        inner,
+        Cipher::new(psk, &salt, INFO_C2S),
+        Cipher::new(psk, &salt, INFO_S2C),
+    ))
+}
+
+/// 服务端握手
+///
+/// PSK 不匹配时握手本身不会失败，而是在解密第一个数据块时报错
+pub async fn accept<S>(mut inner: S, psk: &[u8]) -> io::Result<CryptoStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut salt_c = [0u8; SALT_LEN];
+    inner.read_exact(&mut salt_c).await?;
+
+    let mut salt_s = [0u8; SALT_LEN];
+    rand::rng().fill_bytes(&mut salt_s);
+    inner.write_all(&salt_s).await?;
+
+    let salt = [salt_c, salt_s].concat();
+    Ok(CryptoStream::new(
+        inner,
+        Cipher::new(psk, &salt, INFO_S2C),
+        Cipher::new(psk, &salt, INFO_C2S),
+    ))
+}
+
+impl<S> CryptoStream<S> {
+    fn new(inner: S, enc: Cipher, dec: Cipher) -> Self {
+        CryptoStream {
+            inner,
+            enc,
+            dec,
+            wbuf: Vec::new(),
+            wpos: 0,
+            rbuf: Vec::new(),
+            chunk_len: None,
+            plain: Vec::new(),
+            plain_pos: 0,
+        }
+    }
+
+    /// 从 rbuf 中解出一个完整的数据块，数据不足时返回 false
+    fn decode_chunk(&mut self) -> io::Result<bool> {
+        if self.chunk_len.is_none() {
+            if self.rbuf.len() < 2 + TAG_LEN {
+                return Ok(false);
+            }
+            let mut len: Vec<u8> = self.rbuf.drain(..2 + TAG_LEN).collect();
+            self.dec.open(&mut len)?;
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            if len == 0 || len > MAX_CHUNK {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid tunnel chunk length",
+                ));
+            }
+            self.chunk_len = Some(len);
+        }
+
+        let len = self.chunk_len.unwrap_or_default();
+        if self.rbuf.len() < len + TAG_LEN {
+            return Ok(false);
+        }
+        let mut payload: Vec<u8> = self.rbuf.drain(..len + TAG_LEN).collect();
+        self.dec.open(&mut payload)?;
+        self.plain = payload;
+        self.plain_pos = 0;
+        self.chunk_len = None;
+        Ok(true)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> CryptoStream<S> {
+    /// 把已加密的数据写入底层连接
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.wpos < self.wbuf.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.wbuf[self.wpos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.wpos += n;
+        }
+        self.wbuf.clear();
+        self.wpos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CryptoStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.plain_pos < this.plain.len() {
+                let n = (this.plain.len() - this.plain_pos).min(buf.remaining());
+                buf.put_slice(&this.plain[this.plain_pos..this.plain_pos + n]);
+                this.plain_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.decode_chunk()? {
+                continue;
+            }
+
+            let mut tmp = [0u8; 8192];
+            let mut tmp_buf = ReadBuf::new(&mut tmp);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut tmp_buf))?;
+            if tmp_buf.filled().is_empty() {
+                if this.rbuf.is_empty() && this.chunk_len.is_none() {
+                    return Poll::Ready(Ok(()));
+                }
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            this.rbuf.extend_from_slice(tmp_buf.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CryptoStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.poll_drain(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let n = buf.len().min(MAX_CHUNK);
+        let mut len = (n as u16).to_be_bytes().to_vec();
+        this.enc.seal(&mut len);
+        let mut payload = buf[..n].to_vec();
+        this.enc.seal(&mut payload);
+        this.wbuf.extend_from_slice(&len);
+        this.wbuf.extend_from_slice(&payload);
+
+        // 数据已被接收，尽量立即发出；Pending 时留给下次 write/flush
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for CryptoStream<S> {}
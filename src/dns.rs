@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::{Instant, timeout};
+use tracing::debug;
+
+use crate::config::DnsConfig;
+use crate::rules::{DomainSet, Rules};
+
+pub const DNS_PORT: u16 = 53;
+/// 等待上游应答的时间
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 未配置上游时反向解析使用的系统 DNS 服务器
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NXDOMAIN: u8 = 3;
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const TYPE_OPT: u16 = 41;
+const CLASS_IN: u16 = 1;
+
+/// DNS 报文头
+/// +----+-------+---------+---------+---------+---------+
+/// | ID | FLAGS | QDCOUNT | ANCOUNT | NSCOUNT | ARCOUNT |
+/// +----+-------+---------+---------+---------+---------+
+/// | 2  |   2   |    2    |    2    |    2    |    2    |
+/// +----+-------+---------+---------+---------+---------+
+const HEADER_LEN: usize = 12;
+
+/// DNS 解析：拦截 UDP 关联中发往 53 端口的查询，按规则拦截或转发到配置的上游，
+/// 结果在所有关联间共享缓存；CONNECT、UDP 目标和 RESOLVE 命令也经这里解析域名
+#[derive(Debug)]
+pub struct Dns {
+    intercept: bool,
+    upstream: Option<SocketAddr>,
+    routes: Vec<(DomainSet, SocketAddr)>,
+    rules: Arc<Rules>,
+    /// 按 (上游, 问题) 缓存：客户端自己指定的服务器的应答只会用于发往同一服务器的查询
+    cache: Mutex<HashMap<(SocketAddr, Question), CacheEntry>>,
+    cache_size: usize,
+    max_ttl: u64,
+}
+
+/// 缓存键：小写的查询名、类型和类
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Question {
+    name: String,
+    qtype: u16,
+    qclass: u16,
+}
+
+/// 资源记录在报文中的位置
+#[derive(Debug)]
+struct Record {
+    rtype: u16,
+    ttl: usize,   // TTL 字段的偏移
+    rdata: usize, // RDATA 的偏移
+    rdlen: usize,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    response: Vec<u8>,
+    stored: Instant,
+    ttl: u64,
+}
+
+impl Dns {
+    pub fn new(config: &DnsConfig, rules: Arc<Rules>) -> Result<Self, Box<dyn Error>> {
+        let upstream = config.upstream.as_deref().map(parse_upstream).transpose()?;
+        let mut routes = Vec::with_capacity(config.routes.len());
+        for route in &config.routes {
+            routes.push((
+                DomainSet::new(&route.domains),
+                parse_upstream(&route.upstream)?,
+            ));
+        }
+        Ok(Dns {
+            intercept: config.intercept,
+            upstream,
+            routes,
+            rules,
+            cache: Mutex::new(HashMap::new()),
+            cache_size: config.cache_size,
+            max_ttl: config.max_ttl,
+        })
+    }
+
+    /// 是否拦截 UDP 关联中的 DNS 查询
+    pub fn intercept(&self) -> bool {
+        self.intercept
+    }
+
+    /// 处理客户端发往 `server` 的查询，返回要送回客户端的应答
+    ///
+    /// 黑名单中的域名直接回复 NXDOMAIN，上游失败时回复 SERVFAIL；
+    /// 无法解析的报文返回 None，由调用方原样转发
+    pub async fn handle(&self, query: &[u8], server: SocketAddr) -> Option<Vec<u8>> {
+        let (question, _) = parse_question(query)?;
+
+        if self.rules.is_blocked(&question.name) {
+            debug!("dns blocked: {}", question.name);
+            return Some(error_response(query, RCODE_NXDOMAIN));
+        }
+
+        let upstream = self.upstream_for(&question.name).unwrap_or(server);
+        if let Some(mut response) = self.cached(upstream, &question) {
+            debug!("dns cache hit: {} via {}", question.name, upstream);
+            response[..2].copy_from_slice(&query[..2]);
+            return Some(response);
+        }
+
+        match exchange(upstream, query).await {
+            Ok(response) => {
+                self.store(upstream, question, &response);
+                Some(response)
+            }
+            Err(e) => {
+                debug!("dns query {} via {} failed: {}", question.name, upstream, e);
+                Some(error_response(query, RCODE_SERVFAIL))
+            }
+        }
+    }
+
+    /// 解析域名的 IP 地址
+    ///
+    /// 匹配路由规则或配置了默认上游时向上游查询 A / AAAA (共用应答缓存)，
+    /// 否则使用系统解析器
+    pub async fn lookup(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        let Some(upstream) = self.upstream_for(name) else {
+            let addrs = tokio::net::lookup_host((name, 0)).await?;
+            return Ok(addrs.map(|addr| addr.ip()).collect());
+        };
+
+        let (v4, v6) = tokio::join!(
+            self.query(upstream, name, TYPE_A),
+            self.query(upstream, name, TYPE_AAAA)
+        );
+        let mut ips = Vec::new();
+        let mut error = None;
+        for result in [v4, v6] {
+            match result {
+                Ok(response) => ips.extend(answers(&response).filter_map(|record| {
+                    let rdata = &response[record.rdata..record.rdata + record.rdlen];
+                    match (record.rtype, record.rdlen) {
+                        (TYPE_A, 4) => Some(IpAddr::from(<[u8; 4]>::try_from(rdata).ok()?)),
+                        (TYPE_AAAA, 16) => Some(IpAddr::from(<[u8; 16]>::try_from(rdata).ok()?)),
+                        _ => None,
+                    }
+                })),
+                Err(e) => error = Some(e),
+            }
+        }
+        match (ips.is_empty(), error) {
+            (true, Some(e)) => Err(e),
+            (true, None) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no address", name),
+            )),
+            (false, _) => Ok(ips),
+        }
+    }
+
+    /// 反向解析 IP 地址 (PTR)
+    ///
+    /// 优先使用匹配 `in-addr.arpa` / `ip6.arpa` 名字的路由或默认上游，
+    /// 否则查询系统配置的 DNS 服务器
+    pub async fn reverse(&self, ip: IpAddr) -> io::Result<String> {
+        let name = ptr_name(ip);
+        let upstream = match self.upstream_for(&name) {
+            Some(upstream) => upstream,
+            None => system_nameserver().await?,
+        };
+        let response = self.query(upstream, &name, TYPE_PTR).await?;
+        answers(&response)
+            .filter(|record| record.rtype == TYPE_PTR)
+            .find_map(|record| read_name(&response, record.rdata))
+            .map(|(name, _)| name)
+            .filter(|name| !name.is_empty() && name.len() <= 255)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("{} has no PTR record", ip))
+            })
+    }
+
+    /// 向上游查询一条记录，先查缓存；NXDOMAIN 和其他错误码转换为错误
+    async fn query(&self, upstream: SocketAddr, name: &str, qtype: u16) -> io::Result<Vec<u8>> {
+        let question = Question {
+            name: name.trim_end_matches('.').to_ascii_lowercase(),
+            qtype,
+            qclass: CLASS_IN,
+        };
+        let response = match self.cached(upstream, &question) {
+            Some(response) => response,
+            None => {
+                let query = build_query(&question)?;
+                let response = exchange(upstream, &query).await?;
+                self.store(upstream, question, &response);
+                response
+            }
+        };
+        match response[3] & 0x0F {
+            0 => Ok(response),
+            RCODE_NXDOMAIN => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found", name),
+            )),
+            rcode => Err(io::Error::other(format!(
+                "dns query {} failed with rcode {}",
+                name, rcode
+            ))),
+        }
+    }
+
+    /// 路由规则中最具体的一条，其次是默认上游
+    fn upstream_for(&self, name: &str) -> Option<SocketAddr> {
+        self.routes
+            .iter()
+            .filter_map(|(domains, upstream)| Some((domains.longest_match(name)?, *upstream)))
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, upstream)| upstream)
+            .or(self.upstream)
+    }
+
+    /// 取出缓存的应答，TTL 减去已经缓存的时间
+    fn cached(&self, upstream: SocketAddr, question: &Question) -> Option<Vec<u8>> {
+        let key = (upstream, question.clone());
+        let mut cache = self.cache.lock().unwrap();
+        let entry = cache.get(&key)?;
+        let elapsed = entry.stored.elapsed().as_secs();
+        if elapsed >= entry.ttl {
+            cache.remove(&key);
+            return None;
+        }
+        let mut response = entry.response.clone();
+        for offset in ttl_offsets(&response)? {
+            let ttl = u32::from_be_bytes(response[offset..offset + 4].try_into().ok()?);
+            let ttl = ttl.saturating_sub(elapsed as u32);
+            response[offset..offset + 4].copy_from_slice(&ttl.to_be_bytes());
+        }
+        Some(response)
+    }
+
+    /// 缓存 NOERROR / NXDOMAIN 且未截断的应答，有效期为其中最小的 TTL
+    fn store(&self, upstream: SocketAddr, question: Question, response: &[u8]) {
+        if self.cache_size == 0 || response.len() < HEADER_LEN {
+            return;
+        }
+        let truncated = response[2] & 0x02 != 0;
+        let rcode = response[3] & 0x0F;
+        if truncated || (rcode != 0 && rcode != RCODE_NXDOMAIN) {
+            return;
+        }
+        let Some(offsets) = ttl_offsets(response) else {
+            return;
+        };
+        let Some(ttl) = offsets
+            .iter()
+            .map(|&o| {
+                u32::from_be_bytes([
+                    response[o],
+                    response[o + 1],
+                    response[o + 2],
+                    response[o + 3],
+                ])
+            })
+            .min()
+        else {
+            return;
+        };
+        let ttl = (ttl as u64).min(self.max_ttl);
+        if ttl == 0 {
+            return;
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= self.cache_size {
+            cache.retain(|_, entry| entry.stored.elapsed().as_secs() < entry.ttl);
+        }
+        if cache.len() >= self.cache_size {
+            // 仍然满时淘汰最早过期的一条
+            if let Some(oldest) = cache
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored + Duration::from_secs(entry.ttl))
+                .map(|(key, _)| key.clone())
+            {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(
+            (upstream, question),
+            CacheEntry {
+                response: response.to_vec(),
+                stored: Instant::now(),
+                ttl,
+            },
+        );
+    }
+}
+
+/// `ip:port`，省略端口时使用 53
+fn parse_upstream(addr: &str) -> Result<SocketAddr, Box<dyn Error>> {
+    if let Ok(addr) = addr.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let ip: IpAddr = addr
+        .parse()
+        .map_err(|_| format!("invalid dns upstream: {}", addr))?;
+    Ok(SocketAddr::new(ip, DNS_PORT))
+}
+
+/// `/etc/resolv.conf` 中的第一个 nameserver
+async fn system_nameserver() -> io::Result<SocketAddr> {
+    let content = tokio::fs::read_to_string(RESOLV_CONF).await?;
+    content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no nameserver configured"))
+}
+
+/// IP 对应的反向解析域名
+fn ptr_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::with_capacity(72);
+            for byte in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0F, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// 构造带随机 ID、请求递归的单问题查询
+fn build_query(question: &Question) -> io::Result<Vec<u8>> {
+    let mut msg = Vec::with_capacity(HEADER_LEN + question.name.len() + 6);
+    msg.extend_from_slice(&rand::random::<u16>().to_be_bytes());
+    msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]); // RD，QDCOUNT = 1
+    for label in question.name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid domain name: {}", question.name),
+            ));
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&question.qtype.to_be_bytes());
+    msg.extend_from_slice(&question.qclass.to_be_bytes());
+    Ok(msg)
+}
+
+/// 向上游发送查询并等待应答：ID 相同、QR 置位且问题段与查询一致，其余报文丢弃
+async fn exchange(upstream: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+    let bind_addr: SocketAddr = match upstream {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(upstream).await?;
+    socket.send(query).await?;
+
+    let question = read_question(query).map(|(question, _)| question);
+    let mut buf = vec![0u8; 65535];
+    let deadline = Instant::now() + UPSTREAM_TIMEOUT;
+    loop {
+        let len = timeout(deadline - Instant::now(), socket.recv(&mut buf))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        let response = &buf[..len];
+        if len >= HEADER_LEN
+            && response[..2] == query[..2]
+            && response[2] & 0x80 != 0
+            && read_question(response).map(|(question, _)| question) == question
+        {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+        debug!(
+            "dns reply from {} does not match the query, dropped",
+            upstream
+        );
+    }
+}
+
+/// 解析查询的第一个问题，返回问题和问题段结束的位置；应答 (QR 置位) 返回 None
+fn parse_question(msg: &[u8]) -> Option<(Question, usize)> {
+    if msg.len() < HEADER_LEN || msg[2] & 0x80 != 0 {
+        return None;
+    }
+    read_question(msg)
+}
+
+/// 读取报文 (查询或应答) 的第一个问题
+fn read_question(msg: &[u8]) -> Option<(Question, usize)> {
+    if msg.len() < HEADER_LEN || u16::from_be_bytes([msg[4], msg[5]]) == 0 {
+        return None;
+    }
+    let (name, pos) = read_name(msg, HEADER_LEN)?;
+    let qtype = u16::from_be_bytes([*msg.get(pos)?, *msg.get(pos + 1)?]);
+    let qclass = u16::from_be_bytes([*msg.get(pos + 2)?, *msg.get(pos + 3)?]);
+    Some((
+        Question {
+            name: name.to_ascii_lowercase(),
+            qtype,
+            qclass,
+        },
+        pos + 4,
+    ))
+}
+
+/// 读取 `pos` 处的域名 (支持压缩指针)，返回域名和其后的位置
+fn read_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // 限制跳转次数，防止指针成环
+    for _ in 0..128 {
+        let len = *msg.get(pos)? as usize;
+        match len & 0xC0 {
+            0xC0 => {
+                let target = (len & 0x3F) << 8 | *msg.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                pos = target;
+            }
+            0x00 if len == 0 => {
+                let name = labels.join(".");
+                return Some((name, end.unwrap_or(pos + 1)));
+            }
+            0x00 => {
+                let label = msg.get(pos + 1..pos + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// 依次列出回答、授权和附加段的所有资源记录
+fn records(msg: &[u8]) -> Option<Vec<Record>> {
+    if msg.len() < HEADER_LEN {
+        return None;
+    }
+    let count = |i: usize| u16::from_be_bytes([msg[i], msg[i + 1]]) as usize;
+    let total = count(6) + count(8) + count(10);
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..count(4) {
+        pos = read_name(msg, pos)?.1 + 4;
+    }
+    let mut records = Vec::with_capacity(total);
+    for _ in 0..total {
+        pos = read_name(msg, pos)?.1;
+        let rtype = u16::from_be_bytes([*msg.get(pos)?, *msg.get(pos + 1)?]);
+        let rdlen = u16::from_be_bytes([*msg.get(pos + 8)?, *msg.get(pos + 9)?]) as usize;
+        records.push(Record {
+            rtype,
+            ttl: pos + 4,
+            rdata: pos + 10,
+            rdlen,
+        });
+        pos += 10 + rdlen;
+        if pos > msg.len() {
+            return None;
+        }
+    }
+    Some(records)
+}
+
+/// 回答段的资源记录，报文无法解析时为空
+fn answers(msg: &[u8]) -> impl Iterator<Item = Record> {
+    let count = u16::from_be_bytes([msg[6], msg[7]]) as usize;
+    records(msg).unwrap_or_default().into_iter().take(count)
+}
+
+/// 所有资源记录 (OPT 除外) 的 TTL 字段在报文中的位置
+fn ttl_offsets(msg: &[u8]) -> Option<Vec<usize>> {
+    Some(
+        records(msg)?
+            .into_iter()
+            .filter(|record| record.rtype != TYPE_OPT)
+            .map(|record| record.ttl)
+            .collect(),
+    )
+}
+
+/// 由查询构造只含问题段的错误应答
+fn error_response(query: &[u8], rcode: u8) -> Vec<u8> {
+    let end = parse_question(query).map_or(HEADER_LEN, |(_, end)| end);
+    let mut response = query[..end.min(query.len())].to_vec();
+    response[2] = 0x80 | (query[2] & 0x79); // QR，保留 OPCODE 和 RD
+    response[3] = 0x80 | rcode; // RA
+    response[4..6].copy_from_slice(&1u16.to_be_bytes());
+    response[6..HEADER_LEN].fill(0);
+    response
+}
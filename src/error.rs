@@ -0,0 +1,172 @@
+use std::fmt;
+use std::io;
+
+use crate::consts::*;
+
+/// SOCKS5 会话中的失败
+///
+/// 每种失败对应一个应答码 ([`SocksError::reply_code`]) 和一个日志/指标标签 ([`SocksError::label`])，
+/// 协商和认证阶段的失败没有应答 (协议本身不允许)，应答码只用于统计
+#[derive(Debug)]
+pub enum SocksError {
+    /// 客户端不是 SOCKS5 (或认证子协商版本不对)
+    UnsupportedVersion(u8),
+    /// 报文格式错误
+    Protocol(String),
+    /// 客户端没有提供可接受的认证方法
+    NoAcceptableMethod,
+    /// 用户名或密码错误
+    AuthFailed(String),
+    CommandNotSupported(u8),
+    AddressTypeNotSupported(u8),
+    /// 被访问规则 (黑名单、名字冲突等) 拒绝
+    RuleDenied(String),
+    /// 超出 UDP 关联数上限
+    AssociationLimit(String),
+    /// 目标域名解析失败
+    Dns(String, io::Error),
+    ConnectionRefused(String),
+    NetworkUnreachable(String),
+    HostUnreachable(String),
+    /// 连接或解析目标超时，或客户端在协商、认证、请求阶段超时
+    TimedOut(String),
+    /// 下一跳 (远端节点、反向隧道代理端) 返回的失败应答，原样转给客户端
+    Upstream(String, u8),
+    /// 其他 I/O 错误，包括客户端连接本身的读写
+    Io(io::Error),
+}
+
+impl SocksError {
+    /// 所有标签，顺序与 [`SocksError::index`] 一致
+    pub const LABELS: [&'static str; 15] = [
+        "unsupported_version",
+        "protocol",
+        "no_acceptable_method",
+        "auth_failed",
+        "command_not_supported",
+        "address_type_not_supported",
+        "rule_denied",
+        "association_limit",
+        "dns",
+        "connection_refused",
+        "network_unreachable",
+        "host_unreachable",
+        "timed_out",
+        "upstream",
+        "io",
+    ];
+
+    /// 按出站连接的 io 错误分类
+    pub fn connect(target: impl fmt::Display, e: io::Error) -> Self {
+        let target = target.to_string();
+        match e.kind() {
+            io::ErrorKind::ConnectionRefused => SocksError::ConnectionRefused(target),
+            io::ErrorKind::TimedOut => SocksError::NetworkUnreachable(target),
+            io::ErrorKind::PermissionDenied => SocksError::RuleDenied(target),
+            _ => SocksError::HostUnreachable(format!("{} ({})", target, e)),
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        match self {
+            SocksError::UnsupportedVersion(_) => 0,
+            SocksError::Protocol(_) => 1,
+            SocksError::NoAcceptableMethod => 2,
+            SocksError::AuthFailed(_) => 3,
+            SocksError::CommandNotSupported(_) => 4,
+            SocksError::AddressTypeNotSupported(_) => 5,
+            SocksError::RuleDenied(_) => 6,
+            SocksError::AssociationLimit(_) => 7,
+            SocksError::Dns(..) => 8,
+            SocksError::ConnectionRefused(_) => 9,
+            SocksError::NetworkUnreachable(_) => 10,
+            SocksError::HostUnreachable(_) => 11,
+            SocksError::TimedOut(_) => 12,
+            SocksError::Upstream(..) => 13,
+            SocksError::Io(_) => 14,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        Self::LABELS[self.index()]
+    }
+
+    pub fn reply_code(&self) -> u8 {
+        match self {
+            SocksError::CommandNotSupported(_) => REP_COMMAND_NOT_SUPPORTED,
+            SocksError::AddressTypeNotSupported(_) => REP_ADDRESS_TYPE_NOT_SUPPORTED,
+            SocksError::RuleDenied(_) | SocksError::AssociationLimit(_) => {
+                REP_CONNECTION_NOT_ALLOWED
+            }
+            SocksError::Dns(..) | SocksError::HostUnreachable(_) => REP_HOST_UNREACHABLE,
+            SocksError::ConnectionRefused(_) => REP_CONNECTION_REFUSED,
+            SocksError::NetworkUnreachable(_) => REP_NETWORK_UNREACHABLE,
+            SocksError::TimedOut(_) => REP_TTL_EXPIRED,
+            SocksError::Upstream(_, rep) => *rep,
+            _ => REP_GENERAL_FAILURE,
+        }
+    }
+
+    /// 发给客户端的失败应答 (BND 为全 0 的 IPv4 地址)
+    pub fn reply(&self) -> [u8; 10] {
+        [
+            SOCKS_VERSION,
+            self.reply_code(),
+            0x00,
+            ATYP_IPV4,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]
+    }
+}
+
+impl fmt::Display for SocksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocksError::UnsupportedVersion(ver) => write!(f, "unsupported version: 0x{:02x}", ver),
+            SocksError::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            SocksError::NoAcceptableMethod => write!(f, "no acceptable auth method"),
+            SocksError::AuthFailed(user) => write!(f, "authentication failed: {}", user),
+            SocksError::CommandNotSupported(cmd) => {
+                write!(f, "command not supported: 0x{:02x}", cmd)
+            }
+            SocksError::AddressTypeNotSupported(atyp) => {
+                write!(f, "address type not supported: 0x{:02x}", atyp)
+            }
+            SocksError::RuleDenied(target) => write!(f, "denied by rules: {}", target),
+            SocksError::AssociationLimit(owner) => {
+                write!(f, "UDP association limit reached: {}", owner)
+            }
+            SocksError::Dns(name, e) => write!(f, "resolve {} failed: {}", name, e),
+            SocksError::ConnectionRefused(target) => write!(f, "connection refused: {}", target),
+            SocksError::NetworkUnreachable(target) => {
+                write!(f, "network unreachable: {}", target)
+            }
+            SocksError::HostUnreachable(target) => write!(f, "host unreachable: {}", target),
+            SocksError::TimedOut(target) => write!(f, "timed out: {}", target),
+            SocksError::Upstream(target, rep) => {
+                write!(f, "{} failed: reply 0x{:02x}", target, rep)
+            }
+            SocksError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SocksError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SocksError::Dns(_, e) | SocksError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SocksError {
+    fn from(e: io::Error) -> Self {
+        SocksError::Io(e)
+    }
+}
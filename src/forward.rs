@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, error, info, warn};
+
+use crate::auth::UserConfig;
+use crate::config::{ForwardConfig, ForwardProtocol};
+use crate::consts::*;
+use crate::handler;
+use crate::limits::{AssociationGuard, TokenBucket};
+use crate::metrics::{self, UdpDrop};
+use crate::protocol::{self, Address, SocksRequest};
+use crate::upstream::Upstream;
+
+/// 每个 UDP 会话排队等待发往目标的报文上限
+const UDP_SESSION_QUEUE: usize = 64;
+
+/// 绑定一条静态转发的监听并在后台运行
+pub async fn start(forward: ForwardConfig, config: Arc<UserConfig>) -> Result<(), Box<dyn Error>> {
+    let (address, port) = protocol::parse_target(&forward.target)
+        .ok_or_else(|| format!("invalid forward target: {}", forward.target))?;
+    let target = SocksRequest {
+        cmd: CMD_CONNECT,
+        address,
+        port,
+    };
+    let via = forward
+        .via
+        .iter()
+        .map(|url| Upstream::parse(url))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match forward.protocol {
+        ForwardProtocol::Tcp => {
+            let listener = TcpListener::bind(&forward.listen).await?;
+            tokio::spawn(async move {
+                if let Err(e) = serve_tcp(listener, target, via, config).await {
+                    error!("Forward listener {} stopped: {}", forward.listen, e);
+                }
+            });
+        }
+        ForwardProtocol::Udp => {
+            if !via.is_empty() {
+                return Err(format!("udp forward {} does not support via", forward.listen).into());
+            }
+            let socket = UdpSocket::bind(&forward.listen).await?;
+            tokio::spawn(async move {
+                if let Err(e) = serve_udp(socket, target, config).await {
+                    error!("Forward listener {} stopped: {}", forward.listen, e);
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
+async fn serve_tcp(
+    listener: TcpListener,
+    target: SocksRequest,
+    via: Vec<Upstream>,
+    config: Arc<UserConfig>,
+) -> Result<(), Box<dyn Error>> {
+    info!("Forwarding tcp {} -> {}", listener.local_addr()?, target);
+    let target = Arc::new(target);
+    let via: Arc<[Upstream]> = via.into();
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let target = target.clone();
+        let via = via.clone();
+        let config = config.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handler::process_forward(socket, &target, &via, config.as_ref()).await {
+                error!("[Error] from {:?} [{}] : {}", addr, e.label(), e);
+            }
+        });
+    }
+}
+
+/// 每个客户端地址一个会话，出站 socket 只接收目标的回包；
+/// 会话数、速率、报文大小沿用 UDP ASSOCIATE 的限制
+async fn serve_udp(
+    socket: UdpSocket,
+    target: SocksRequest,
+    config: Arc<UserConfig>,
+) -> Result<(), Box<dyn Error>> {
+    if let Address::Domain(domain) = &target.address
+        && config.rules.is_blocked(domain)
+    {
+        return Err(format!("udp forward target {} is blocked", target).into());
+    }
+    info!("Forwarding udp {} -> {}", socket.local_addr()?, target);
+    let socket = Arc::new(socket);
+    let target = Arc::new(target);
+    let mut sessions: HashMap<SocketAddr, mpsc::Sender<Vec<u8>>> = HashMap::new();
+    let mut buf = vec![0u8; MAX_UDP_SIZE as usize];
+
+    loop {
+        let (len, client) = socket.recv_from(&mut buf).await?;
+        if len > config.udp.max_datagram_size {
+            metrics::udp_drop(UdpDrop::Oversized);
+            continue;
+        }
+
+        if let Some(tx) = sessions.get(&client)
+            && !tx.is_closed()
+        {
+            if tx.try_send(buf[..len].to_vec()).is_err() {
+                debug!("udp forward session {} queue full", client);
+            }
+            continue;
+        }
+
+        sessions.retain(|_, tx| !tx.is_closed());
+        let owner = client.ip().to_canonical().to_string();
+        let Some(guard) = config.associations.acquire(&owner, &config.udp) else {
+            debug!("udp forward session for {} rejected: limit reached", client);
+            metrics::udp_drop(UdpDrop::Associations);
+            continue;
+        };
+        let (tx, rx) = mpsc::channel(UDP_SESSION_QUEUE);
+        let _ = tx.try_send(buf[..len].to_vec());
+        sessions.insert(client, tx);
+        tokio::spawn(udp_session(
+            socket.clone(),
+            client,
+            target.clone(),
+            rx,
+            guard,
+            config.clone(),
+        ));
+    }
+}
+
+async fn udp_session(
+    socket: Arc<UdpSocket>,
+    client: SocketAddr,
+    target: Arc<SocksRequest>,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    _guard: AssociationGuard,
+    config: Arc<UserConfig>,
+) {
+    let session_timeout = Duration::from_secs(config.udp.session_timeout);
+    let Some((outbound, target)) = open_outbound(&target, &config).await else {
+        // 解析或绑定失败的结果在会话内保留：之后的报文直接丢弃，
+        // 直到客户端安静一个会话周期，避免每个报文都重新解析、打一条告警
+        loop {
+            tokio::select! {
+                data = rx.recv() => if data.is_none() { break },
+                _ = sleep(session_timeout) => break,
+            }
+        }
+        return;
+    };
+    debug!("udp forward session {} -> {}", client, target);
+
+    let mut rate_out = TokenBucket::new(config.udp.rate_limit);
+    let mut rate_in = TokenBucket::new(config.udp.rate_limit);
+    let mut buf = vec![0u8; MAX_UDP_SIZE as usize];
+    loop {
+        tokio::select! {
+            data = rx.recv() => {
+                let Some(data) = data else { break };
+                if !rate_out.take() {
+                    metrics::udp_drop(UdpDrop::RateLimited);
+                    continue;
+                }
+                if let Err(e) = outbound.send(&data).await {
+                    debug!("udp send to {} failed: {}", target, e);
+                }
+            }
+            result = outbound.recv(&mut buf) => match result {
+                Ok(len) => {
+                    if !rate_in.take() {
+                        metrics::udp_drop(UdpDrop::RateLimited);
+                        continue;
+                    }
+                    if let Err(e) = socket.send_to(&buf[..len], client).await {
+                        debug!("udp reply to {} failed: {}", client, e);
+                    }
+                }
+                Err(e) => debug!("udp recv from {} failed: {}", target, e),
+            },
+            _ = sleep(session_timeout) => {
+                debug!("udp forward session {} -> {} expired", client, target);
+                break;
+            }
+        }
+    }
+}
+
+/// 解析目标并建立出站 socket，失败时记录一次日志并返回 None
+async fn open_outbound(
+    target: &SocksRequest,
+    config: &UserConfig,
+) -> Option<(UdpSocket, SocketAddr)> {
+    let ip = match &target.address {
+        Address::Domain(domain) => {
+            let resolve_timeout = Duration::from_secs(config.timeout as u64);
+            match timeout(resolve_timeout, config.dns.lookup(domain)).await {
+                Ok(Ok(ips)) if !ips.is_empty() => ips[0],
+                Ok(Ok(_)) => {
+                    warn!("udp forward resolve {}: no addresses", domain);
+                    return None;
+                }
+                Ok(Err(e)) => {
+                    warn!("udp forward resolve {} failed: {}", domain, e);
+                    return None;
+                }
+                Err(_) => {
+                    warn!("udp forward resolve {} timed out", domain);
+                    return None;
+                }
+            }
+        }
+        address => address.to_socket_addr(target.port)?.ip(),
+    };
+    let target = SocketAddr::new(ip, target.port);
+    let bind_addr: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let outbound = match UdpSocket::bind(bind_addr).await {
+        Ok(outbound) => outbound,
+        Err(e) => {
+            warn!("udp forward bind failed: {}", e);
+            return None;
+        }
+    };
+    if let Err(e) = outbound.connect(target).await {
+        warn!("udp forward connect {} failed: {}", target, e);
+        return None;
+    }
+    Some((outbound, target))
+}
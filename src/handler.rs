@@ -1,4 +1,6 @@
-use std::error::Error;
+use std::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
@@ -6,33 +8,102 @@ use tokio::time::timeout;
 use tracing::{debug, error, info, warn};
 
 // 引入我们封装好的模块
-use crate::auth::{self, UserConfig};
+use crate::auth::{self, User, UserConfig};
+use crate::config::UdpConfig;
 use crate::consts::*;
-use crate::protocol::SocksRequest;
-use crate::udp::UDPRelay;
+use crate::error::SocksError;
+use crate::idle;
+use crate::limits::AssociationGuard;
+use crate::metrics::{self, UdpDrop};
+use crate::mux::Session;
+use crate::protocol::{self, Address, SocksRequest};
+use crate::proxy_protocol;
+use crate::reverse;
+use crate::transport::Stream;
+use crate::udp::{self, UDPRelay};
+use crate::upstream::{self, Upstream};
 
-pub async fn process(mut socket: TcpStream, config: &UserConfig) -> Result<(), Box<dyn Error>> {
-    // ==========================================
-    // 阶段 1: 协商 (Handshake)
-    // ==========================================
+pub async fn process<S: Stream>(
+    mut socket: S,
+    peer_addr: SocketAddr,
+    config: &UserConfig,
+) -> Result<(), SocksError> {
+    let result = async {
+        let (request, user) = handshake(&mut socket, config).await?;
 
-    let mut buf = [0u8; 1];
-    socket.read_exact(&mut buf).await?;
-    if buf[0] != SOCKS_VERSION {
-        return Err("仅支持 SOCKS5 协议".into());
+        // 根据命令分发到不同的处理函数
+        match request.cmd {
+            CMD_CONNECT => handle_tcp_connect(socket, peer_addr, request, user, config).await,
+            CMD_UDP_ASSOCIATE => {
+                handle_udp_associate(socket, peer_addr, request, user, config).await
+            }
+            CMD_UDP_OVER_TCP => handle_udp_stream(socket, peer_addr, user, config).await,
+            CMD_RESOLVE | CMD_RESOLVE_PTR => handle_resolve(socket, request, config).await,
+            cmd => {
+                warn!("不支持的命令: {}", cmd);
+                Err(reject(&mut socket, SocksError::CommandNotSupported(cmd)).await)
+            }
+        }
     }
+    .await;
 
-    // 读取 NMETHODS
-    let mut buf = [0u8; 1];
-    socket.read_exact(&mut buf).await?;
-    let nmethods = buf[0] as usize;
+    if let Err(e) = &result {
+        metrics::session_error(e);
+    }
+    result
+}
 
-    let mut methods = vec![0u8; nmethods];
-    socket.read_exact(&mut methods).await?;
+/// 处理来自本地节点 (或反向隧道代理端) 的隧道会话
+///
+/// 隧道本身已由 PSK 完成认证，跳过协商直接读取请求；
+/// UDP ASSOCIATE 的数据报按帧走隧道本身，而不是另开 UDP 端口；
+/// UDP 关联数按本地节点的地址统计；`session` 用于代理端注册反向隧道
+pub async fn process_tunnel<S: Stream>(
+    mut socket: S,
+    peer_addr: SocketAddr,
+    session: &Session,
+    config: &UserConfig,
+) -> Result<(), SocksError> {
+    let result = async {
+        let timeout = config.timeouts.request;
+        let request = within(timeout, "request", read_request(&mut socket)).await?;
+
+        match request.cmd {
+            CMD_CONNECT => handle_tcp_connect(socket, peer_addr, request, None, config).await,
+            CMD_UDP_ASSOCIATE | CMD_UDP_OVER_TCP => {
+                handle_udp_stream(socket, peer_addr, None, config).await
+            }
+            CMD_RESOLVE | CMD_RESOLVE_PTR => handle_resolve(socket, request, config).await,
+            CMD_REGISTER => reverse::register(socket, request, session, config).await,
+            cmd => {
+                warn!("不支持的命令: {}", cmd);
+                Err(reject(&mut socket, SocksError::CommandNotSupported(cmd)).await)
+            }
+        }
+    }
+    .await;
+
+    if let Err(e) = &result {
+        metrics::session_error(e);
+    }
+    result
+}
+
+/// 完成协商与认证，读取客户端请求；同时返回认证通过的用户 (无认证时为 None)
+pub async fn handshake<'a, S: Stream>(
+    socket: &mut S,
+    config: &'a UserConfig,
+) -> Result<(SocksRequest, Option<&'a User>), SocksError> {
+    // ==========================================
+    // 阶段 1: 协商 (Handshake)
+    // ==========================================
+
+    let timeouts = &config.timeouts;
+    let methods = within(timeouts.greeting, "greeting", read_greeting(socket)).await?;
 
     let mut should_auth = false;
 
-    if let Some(_user_config) = &config.user {
+    if !config.users.is_empty() {
         if methods.contains(&METHOD_PASSWORD) {
             should_auth = true;
             socket.write_all(&[SOCKS_VERSION, METHOD_PASSWORD]).await?;
@@ -40,101 +111,129 @@ pub async fn process(mut socket: TcpStream, config: &UserConfig) -> Result<(), B
             socket
                 .write_all(&[SOCKS_VERSION, METHOD_NO_ACCEPTABLE])
                 .await?;
-            return Err("client don't support auth".into());
+            return Err(SocksError::NoAcceptableMethod);
         }
     } else {
         socket.write_all(&[SOCKS_VERSION, METHOD_NO_AUTH]).await?;
     }
 
+    let mut user = None;
     if should_auth {
-        auth::perform_password_auth(&mut socket, config.user.as_ref().unwrap()).await?;
+        let auth = auth::perform_password_auth(socket, &config.users);
+        user = Some(within(timeouts.auth, "auth", auth).await?);
     }
     // ==========================================
     // 阶段 2: 请求 (Request)
     // ==========================================
 
-    let request = SocksRequest::read_from(&mut socket).await?;
+    let request = within(timeouts.request, "request", read_request(socket)).await?;
+    Ok((request, user))
+}
 
-    // 根据命令分发到不同的处理函数
-    match request.cmd {
-        CMD_CONNECT => {
-            handle_tcp_connect(socket, request, config).await?;
-        }
-        CMD_UDP_ASSOCIATE => {
-            handle_udp_associate(socket, request).await?;
-        }
-        _ => {
-            warn!("不支持的命令: {}", request.cmd);
-            let reply = [
-                SOCKS_VERSION,
-                REP_COMMAND_NOT_SUPPORTED,
-                0x00,
-                ATYP_IPV4,
-                0,
-                0,
-                0,
-                0,
-                0,
-                0,
-            ];
-            socket.write_all(&reply).await?;
-            return Err("Unsupported Command".into());
-        }
+/// 读取方法协商：VER NMETHODS METHODS
+async fn read_greeting<S: Stream>(socket: &mut S) -> Result<Vec<u8>, SocksError> {
+    let mut buf = [0u8; 1];
+    socket.read_exact(&mut buf).await?;
+    if buf[0] != SOCKS_VERSION {
+        return Err(SocksError::UnsupportedVersion(buf[0]));
     }
 
-    Ok(())
+    // 读取 NMETHODS
+    let mut buf = [0u8; 1];
+    socket.read_exact(&mut buf).await?;
+    let nmethods = buf[0] as usize;
+
+    let mut methods = vec![0u8; nmethods];
+    socket.read_exact(&mut methods).await?;
+    Ok(methods)
+}
+
+/// 客户端须在 `secs` 秒内完成 `phase`，0 表示不限制
+pub(crate) async fn within<T>(
+    secs: u64,
+    phase: &str,
+    future: impl Future<Output = Result<T, SocksError>>,
+) -> Result<T, SocksError> {
+    if secs == 0 {
+        return future.await;
+    }
+    match timeout(Duration::from_secs(secs), future).await {
+        Ok(result) => result,
+        Err(_) => Err(SocksError::TimedOut(format!(
+            "client {} ({}s)",
+            phase, secs
+        ))),
+    }
+}
+
+/// 读取请求；地址类型未知时应答 0x08 (之后的字节无法解析，只能关闭连接)
+async fn read_request<S: Stream>(socket: &mut S) -> Result<SocksRequest, SocksError> {
+    match SocksRequest::read_from(socket).await {
+        Err(e @ SocksError::AddressTypeNotSupported(_)) => Err(reject(socket, e).await),
+        result => result,
+    }
+}
+
+/// 把失败应答发给客户端，原样返回错误
+async fn reject<S: Stream>(socket: &mut S, e: SocksError) -> SocksError {
+    let _ = socket.write_all(&e.reply()).await;
+    e
 }
 
 /// 处理 TCP CONNECT 命令
-async fn handle_tcp_connect(
-    mut socket: TcpStream,
+async fn handle_tcp_connect<S: Stream>(
+    mut socket: S,
+    peer_addr: SocketAddr,
     request: SocksRequest,
+    user: Option<&User>,
     config: &UserConfig,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), SocksError> {
     let target = request.to_string();
     info!("TCP Connect to: {}", target);
 
+    if let Address::Domain(domain) = &request.address
+        && config.rules.is_blocked(domain)
+    {
+        warn!("目标被规则拦截: {}", target);
+        return Err(reject(&mut socket, SocksError::RuleDenied(target)).await);
+    }
+
+    // `<name>.tunnel` 经反向隧道由代理端出站
+    if let Address::Domain(domain) = &request.address
+        && let Some(name) = reverse::tunnel_name(domain)
+    {
+        return reverse::connect(socket, name, &request, config).await;
+    }
+
     // ==========================================
     // 阶段 3: TCP 转发
     // ==========================================
     let connect_timeout = Duration::from_secs(config.timeout as u64);
-    let server_socket_result = timeout(connect_timeout, TcpStream::connect(&target)).await;
-
-    let mut server_socket = match server_socket_result {
+    let mut server_socket = match timeout(connect_timeout, connect(&request, config)).await {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => {
+            error!("目标主机连接失败：{}", e);
+            return Err(reject(&mut socket, e).await);
+        }
         Err(_) => {
             warn!("连接目标超时 ({}s): {}", config.timeout, target);
-            let reply = [
-                SOCKS_VERSION,
-                REP_TTL_EXPIRED,
-                0x00,
-                ATYP_IPV4,
-                0,
-                0,
-                0,
-                0,
-                0,
-                0,
-            ];
-            let _ = socket.write_all(&reply).await;
-            return Err("连接目标超时".into());
-        }
-        Ok(connect_result) => match connect_result {
-            Ok(s) => s,
-            Err(e) => {
-                let rep = match e.kind() {
-                    std::io::ErrorKind::ConnectionRefused => REP_CONNECTION_REFUSED,
-                    std::io::ErrorKind::TimedOut => REP_NETWORK_UNREACHABLE,
-                    std::io::ErrorKind::PermissionDenied => REP_CONNECTION_NOT_ALLOWED,
-                    _ => REP_HOST_UNREACHABLE,
-                };
-                error!("目标主机连接失败：{}({})", target, e);
-                let reply = [SOCKS_VERSION, rep, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0];
-                let _ = socket.write_all(&reply).await;
-                return Err(e.into());
-            }
-        },
+            return Err(reject(&mut socket, SocksError::TimedOut(target)).await);
+        }
     };
 
+    // 按规则先在出站连接上写 PROXY protocol 头部，让后端得知真实的客户端地址和用户
+    if let Ok(dest) = server_socket.peer_addr()
+        && let Some(version) = config.rules.proxy_header(&request.address, dest.ip())
+    {
+        let user = user.map(|u| u.username.as_str());
+        let header = proxy_protocol::header(version, peer_addr, dest, user);
+        if let Err(e) = server_socket.write_all(&header).await {
+            error!("发送 PROXY 头部失败：{}({})", target, e);
+            return Err(reject(&mut socket, SocksError::Io(e)).await);
+        }
+        debug!("PROXY v{} header sent to {}", version, dest);
+    }
+
     // 告诉客户端连接成功
     let reply = [
         SOCKS_VERSION,
@@ -150,41 +249,193 @@ async fn handle_tcp_connect(
     ];
     socket.write_all(&reply).await?;
 
-    transfer(&mut socket, &mut server_socket).await?;
+    transfer(&mut socket, &mut server_socket, config).await?;
 
     Ok(())
 }
 
-/// 处理 UDP ASSOCIATE 命令
-async fn handle_udp_associate(
+/// 处理透明代理的连接：目标来自 netfilter，没有协商和应答，
+/// 出站与 CONNECT 共用 `connect` 和 `transfer`
+pub async fn process_transparent(
     mut socket: TcpStream,
-    _request: SocksRequest, // UDP Associate 请求中的 IP/Port 通常被忽略，或者是客户端希望发送 UDP 的源地址
-) -> Result<(), Box<dyn Error>> {
-    let client_ip = socket.peer_addr()?.ip();
-    info!("UDP Associate request from: {}", client_ip);
+    target: SocketAddr,
+    config: &UserConfig,
+) -> Result<(), SocksError> {
+    info!("Transparent connect to: {}", target);
+
+    let request = SocksRequest {
+        cmd: CMD_CONNECT,
+        address: Address::from(target.ip()),
+        port: target.port(),
+    };
+    let connect_timeout = Duration::from_secs(config.timeout as u64);
+    let mut server_socket = timeout(connect_timeout, connect(&request, config))
+        .await
+        .map_err(|_| SocksError::TimedOut(target.to_string()))??;
+
+    transfer(&mut socket, &mut server_socket, config).await
+}
+
+/// 处理静态转发的连接：目标固定，没有协商和应答，可经上游 SOCKS5 代理链出站
+pub async fn process_forward(
+    mut socket: TcpStream,
+    target: &SocksRequest,
+    via: &[Upstream],
+    config: &UserConfig,
+) -> Result<(), SocksError> {
+    info!("Forward to: {}", target);
+
+    if let Address::Domain(domain) = &target.address
+        && config.rules.is_blocked(domain)
+    {
+        return Err(SocksError::RuleDenied(target.to_string()));
+    }
+
+    let connect_timeout = Duration::from_secs(config.timeout as u64);
+    let mut server_socket = timeout(connect_timeout, dial(target, via, config))
+        .await
+        .map_err(|_| SocksError::TimedOut(target.to_string()))??;
+
+    transfer(&mut socket, &mut server_socket, config).await
+}
+
+/// 直连目标，或先连上游代理链的第一跳再逐跳 CONNECT
+async fn dial(
+    target: &SocksRequest,
+    via: &[Upstream],
+    config: &UserConfig,
+) -> Result<TcpStream, SocksError> {
+    let Some(first) = via.first() else {
+        return connect(target, config).await;
+    };
+    let mut stream = connect(&first.request(), config).await?;
+    upstream::connect_chain(&mut stream, via, target).await?;
+    Ok(stream)
+}
+
+/// 连接目标，域名经共享的解析器解析 (与 UDP 目标和 RESOLVE 一致)
+async fn connect(request: &SocksRequest, config: &UserConfig) -> Result<TcpStream, SocksError> {
+    let addrs: Vec<SocketAddr> = match &request.address {
+        Address::Domain(domain) => config
+            .dns
+            .lookup(domain)
+            .await
+            .map_err(|e| SocksError::Dns(domain.clone(), e))?
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, request.port))
+            .collect(),
+        address => address.to_socket_addr(request.port).into_iter().collect(),
+    };
+    TcpStream::connect(&addrs[..])
+        .await
+        .map_err(|e| SocksError::connect(request, e))
+}
+
+/// 处理 RESOLVE / RESOLVE_PTR 扩展命令：只解析不连接，
+/// 应答的 BND.ADDR 为解析出的 IP 或域名 (BND.PORT 为 0)，之后关闭连接
+async fn handle_resolve<S: Stream>(
+    mut socket: S,
+    request: SocksRequest,
+    config: &UserConfig,
+) -> Result<(), SocksError> {
+    info!(
+        "Resolve request: {:?} (cmd 0x{:02x})",
+        request.address, request.cmd
+    );
+
+    let resolve_timeout = Duration::from_secs(config.timeout as u64);
+    let result = match timeout(resolve_timeout, resolve(&request, config)).await {
+        Ok(result) => result,
+        Err(_) => {
+            warn!("解析超时 ({}s): {:?}", config.timeout, request.address);
+            Err(SocksError::TimedOut(format!("{:?}", request.address)))
+        }
+    };
+
+    match result {
+        Ok(address) => {
+            debug!("Resolved {:?} -> {:?}", request.address, address);
+            socket
+                .write_all(&protocol::reply_address(REP_SUCCESS, &address, 0))
+                .await?;
+            Ok(())
+        }
+        Err(e) => Err(reject(&mut socket, e).await),
+    }
+}
+
+/// RESOLVE 解析域名 (IP 原样返回)，RESOLVE_PTR 反向解析 IP；
+/// 与 CONNECT 一样先检查黑名单
+async fn resolve(request: &SocksRequest, config: &UserConfig) -> Result<Address, SocksError> {
+    match (request.cmd, &request.address) {
+        (CMD_RESOLVE, Address::Domain(domain)) => {
+            if config.rules.is_blocked(domain) {
+                warn!("目标被规则拦截: {}", domain);
+                return Err(SocksError::RuleDenied(domain.clone()));
+            }
+            let ips = config.dns.lookup(domain).await.map_err(|e| {
+                warn!("解析 {} 失败: {}", domain, e);
+                SocksError::Dns(domain.clone(), e)
+            })?;
+            ips.first()
+                .map(|ip| Address::from(*ip))
+                .ok_or_else(|| SocksError::Dns(domain.clone(), std::io::ErrorKind::NotFound.into()))
+        }
+        (CMD_RESOLVE, address) => address
+            .to_socket_addr(0)
+            .map(|addr| Address::from(addr.ip()))
+            .ok_or(SocksError::AddressTypeNotSupported(ATYP_DOMAIN)),
+        (_, address) => {
+            let Some(addr) = address.to_socket_addr(0) else {
+                return Err(SocksError::AddressTypeNotSupported(ATYP_DOMAIN));
+            };
+            let name = config.dns.reverse(addr.ip()).await.map_err(|e| {
+                warn!("反向解析 {} 失败: {}", addr.ip(), e);
+                SocksError::Dns(addr.ip().to_string(), e)
+            })?;
+            if config.rules.is_blocked(&name) {
+                warn!("目标被规则拦截: {}", name);
+                return Err(SocksError::RuleDenied(name));
+            }
+            Ok(Address::Domain(name))
+        }
+    }
+}
+
+/// 处理 UDP ASSOCIATE 命令
+async fn handle_udp_associate<S: Stream>(
+    mut socket: S,
+    peer_addr: SocketAddr,
+    request: SocksRequest, // 请求中的 IP/Port 是客户端将用来发送 UDP 的源地址，全 0 表示未知
+    user: Option<&User>,
+    config: &UserConfig,
+) -> Result<(), SocksError> {
+    let client = udp::expected_client(&request, peer_addr);
+    info!("UDP Associate request from: {} (udp {})", peer_addr, client);
+
+    let udp_config = config.udp_for(user);
+    let _association =
+        acquire_association(&mut socket, peer_addr, user, &udp_config, config).await?;
 
     // 1. 初始化 UDP Relay
     // 这会绑定一个随机 UDP 端口
-    let (relay, listen_addr) = UDPRelay::new(client_ip).await?;
+    let (relay, listen_addr) = match UDPRelay::new(client, &udp_config, config).await {
+        Ok(result) => result,
+        Err(e) => {
+            error!("UDP Relay bind failed: {}", e);
+            return Err(reject(&mut socket, SocksError::Io(e)).await);
+        }
+    };
     let udp_port = listen_addr.port();
 
     info!("UDP Relay started at port: {}", udp_port);
 
     // 2. 告诉客户端 UDP 监听端口
-    // 这里回复 IP 0.0.0.0 (全0)，告诉客户端使用它连接 TCP 时使用的那个服务器 IP
-    let reply = [
-        SOCKS_VERSION,
-        REP_SUCCESS,
-        0x00,
-        ATYP_IPV4,
-        0,
-        0,
-        0,
-        0, // IP
-        (udp_port >> 8) as u8,
-        (udp_port & 0xff) as u8, // Port
-    ];
-    socket.write_all(&reply).await?;
+    // 这里回复全 0 地址 (IPv4 客户端为 0.0.0.0，IPv6 客户端为 ::)，
+    // 告诉客户端使用它连接 TCP 时使用的那个服务器 IP
+    socket
+        .write_all(&protocol::reply(REP_SUCCESS, listen_addr))
+        .await?;
 
     // 3. 并发运行：UDP 转发循环 & TCP 保活监控
     // SOCKS5 规定：当 TCP 断开时，UDP 关联也必须停止
@@ -209,37 +460,119 @@ async fn handle_udp_associate(
     Ok(())
 }
 
-async fn transfer(client: &mut TcpStream, server: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+/// 处理 UDP over TCP (扩展命令或隧道上的 UDP ASSOCIATE)：
+/// 数据报按帧在 TCP 连接中传输，由本端的 UDP socket 出站，回包以同样的帧格式写回
+async fn handle_udp_stream<S: Stream>(
+    mut socket: S,
+    peer_addr: SocketAddr,
+    user: Option<&User>,
+    config: &UserConfig,
+) -> Result<(), SocksError> {
+    let udp_config = config.udp_for(user);
+    let _association =
+        acquire_association(&mut socket, peer_addr, user, &udp_config, config).await?;
+    info!("UDP Relay (stream) started");
+
+    let reply = [
+        SOCKS_VERSION,
+        REP_SUCCESS,
+        0x00,
+        ATYP_IPV4,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ];
+    socket.write_all(&reply).await?;
+    socket.flush().await?;
+
+    udp::run_stream(socket, &udp_config, config).await
+}
+
+/// 为用户 (无认证时为客户端 IP) 占用一个 UDP 关联名额，超出上限时拒绝请求
+async fn acquire_association<S: Stream>(
+    socket: &mut S,
+    peer_addr: SocketAddr,
+    user: Option<&User>,
+    udp_config: &UdpConfig,
+    config: &UserConfig,
+) -> Result<AssociationGuard, SocksError> {
+    let owner = match user {
+        Some(user) => user.username.clone(),
+        None => peer_addr.ip().to_canonical().to_string(),
+    };
+    if let Some(guard) = config.associations.acquire(&owner, udp_config) {
+        return Ok(guard);
+    }
+
+    warn!("UDP association limit reached: {}", owner);
+    metrics::udp_drop(UdpDrop::Associations);
+    Err(reject(socket, SocksError::AssociationLimit(owner)).await)
+}
+
+/// splice 失败过一次后不再使用，改走用户态拷贝
+#[cfg(target_os = "linux")]
+static SPLICE_SUPPORTED: AtomicBool = AtomicBool::new(true);
+
+/// 双向转发，两个方向都超过 `timeouts.idle` 没有数据时关闭
+async fn transfer<S: Stream>(
+    client: &mut S,
+    server: &mut TcpStream,
+    config: &UserConfig,
+) -> Result<(), SocksError> {
+    let idle = Duration::from_secs(config.timeouts.idle);
+
     #[cfg(target_os = "linux")]
+    if config.splice
+        && SPLICE_SUPPORTED.load(Ordering::Relaxed)
+        && let Some(client) = client.as_tcp()
     {
+        use std::os::fd::AsRawFd;
         use tokio_splice::zero_copy_bidirectional;
 
-        // splice 需要文件描述符，tokio 的 TcpStream 实现了 AsRawFd
-        match zero_copy_bidirectional(client, server).await {
+        // splice 需要文件描述符，tokio 的 TcpStream 实现了 AsRawFd；
+        // 数据不经过用户态，空闲时间从两个 socket 的 TCP_INFO 取得
+        let fds = [client.as_raw_fd(), server.as_raw_fd()];
+        let result = tokio::select! {
+            result = zero_copy_bidirectional(client, server) => result,
+            _ = idle::watch(idle, || idle::tcp_idle(fds)) => {
+                info!("TCP 转发空闲超过 {}s，关闭", idle.as_secs());
+                return Ok(());
+            }
+        };
+        match result {
             Ok((up, down)) => {
                 debug!("Splice 传输完成: 上行 {}b, 下行 {}b", up, down);
-                Ok(())
+                return Ok(());
+            }
+            // 内核或沙箱不支持对这类 socket 做 splice 时第一次调用就会失败，数据还没有移动
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS)) => {
+                warn!("splice unavailable ({}), falling back to copy", e);
+                SPLICE_SUPPORTED.store(false, Ordering::Relaxed);
             }
             Err(e) => {
                 error!("Splice 传输错误: {}", e);
-                Err(e.into())
+                return Err(e.into());
             }
         }
     }
 
-    #[cfg(not(target_os = "linux"))]
-    {
-        // 非 Linux (macOS/Windows) 使用普通的用户态拷贝
-        match tokio::io::copy_bidirectional(client, server).await {
-            Ok((up, down)) => {
-                debug!("Copy 传输完成: 上行 {}b, 下行 {}b", up, down);
-                Ok(())
-            }
-            Err(e) => {
-                // copy_bidirectional 有时在断开时会报 ConnectionReset，这其实不算严重错误
-                debug!("Copy 传输中断: {}", e);
-                Ok(())
-            }
+    // 非 Linux (macOS/Windows)、关闭了 splice 或客户端不是裸 TCP (如 WebSocket) 时使用普通的用户态拷贝
+    match idle::copy(client, server, idle).await {
+        Ok(Some((up, down))) => {
+            debug!("Copy 传输完成: 上行 {}b, 下行 {}b", up, down);
+            Ok(())
+        }
+        Ok(None) => {
+            info!("TCP 转发空闲超过 {}s，关闭", idle.as_secs());
+            Ok(())
+        }
+        Err(e) => {
+            // copy_bidirectional 有时在断开时会报 ConnectionReset，这其实不算严重错误
+            debug!("Copy 传输中断: {}", e);
+            Ok(())
         }
     }
 }
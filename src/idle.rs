@@ -0,0 +1,140 @@
+//! TCP 转发的空闲检测
+//!
+//! 用户态拷贝时用 [`Tracked`] 包装两端，读到数据即刷新 [`Activity`]；
+//! splice 的数据不经过用户态，改为查询两个 socket 的 `TCP_INFO`
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// 最近一次读到数据的时间
+pub struct Activity {
+    start: Instant,
+    last: AtomicU64, // 相对 start 的毫秒数
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Activity {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let now = self.start.elapsed().as_millis() as u64;
+        self.last.store(now, Ordering::Relaxed);
+    }
+
+    /// 已经空闲的时长
+    pub fn idle(&self) -> Duration {
+        let last = Duration::from_millis(self.last.load(Ordering::Relaxed));
+        self.start.elapsed().saturating_sub(last)
+    }
+}
+
+/// 读到数据时刷新 [`Activity`] 的流
+pub struct Tracked<'a, S> {
+    inner: &'a mut S,
+    activity: &'a Activity,
+}
+
+impl<'a, S> Tracked<'a, S> {
+    pub fn new(inner: &'a mut S, activity: &'a Activity) -> Self {
+        Tracked { inner, activity }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Tracked<'_, S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut *this.inner).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            this.activity.touch();
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Tracked<'_, S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// 空闲达到 `limit` 时返回，`idle` 给出当前已空闲的时长；`limit` 为 0 时永不返回
+pub async fn watch(limit: Duration, idle: impl Fn() -> Duration) {
+    if limit.is_zero() {
+        return std::future::pending().await;
+    }
+    loop {
+        let quiet = idle();
+        if quiet >= limit {
+            return;
+        }
+        tokio::time::sleep(limit - quiet).await;
+    }
+}
+
+/// 用户态双向拷贝，两个方向都空闲达到 `limit` 时提前结束并返回 None；`limit` 为 0 时不限制
+pub async fn copy<A, B>(a: &mut A, b: &mut B, limit: Duration) -> io::Result<Option<(u64, u64)>>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let activity = Activity::new();
+    let mut a = Tracked::new(a, &activity);
+    let mut b = Tracked::new(b, &activity);
+    tokio::select! {
+        result = tokio::io::copy_bidirectional(&mut a, &mut b) => result.map(Some),
+        _ = watch(limit, || activity.idle()) => Ok(None),
+    }
+}
+
+/// 两个 TCP socket 中较近一次收到数据至今的时长，取不到 `TCP_INFO` 时视为刚有活动
+#[cfg(target_os = "linux")]
+pub fn tcp_idle(fds: [std::os::fd::RawFd; 2]) -> Duration {
+    fds.iter()
+        .map(|&fd| last_data_recv(fd).unwrap_or(Duration::ZERO))
+        .min()
+        .unwrap_or(Duration::ZERO)
+}
+
+#[cfg(target_os = "linux")]
+fn last_data_recv(fd: std::os::fd::RawFd) -> Option<Duration> {
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+    Some(Duration::from_millis(info.tcpi_last_data_recv as u64))
+}
@@ -0,0 +1,36 @@
+//! SOCKS5 服务端库：`proxy` 二进制的全部功能，也可以嵌入到自己的 Tokio 服务中
+//!
+//! 常用入口：[`Server`] 构建并运行服务端，[`protocol`] 中是报文的编解码，
+//! [`handler::handshake`] 和 [`auth::perform_password_auth`] 是协商与认证流程
+
+pub mod auth;
+mod batch;
+pub mod client;
+pub mod config;
+pub mod consts;
+mod crypto;
+pub mod dns;
+pub mod error;
+pub mod forward;
+pub mod handler;
+mod idle;
+pub mod limits;
+pub mod metrics;
+pub mod mux;
+pub mod ports;
+pub mod protocol;
+pub mod proxy_protocol;
+pub mod reverse;
+pub mod rules;
+pub mod server;
+#[cfg(target_os = "linux")]
+pub mod transparent;
+pub mod transport;
+pub mod tunnel;
+pub mod udp;
+pub mod upstream;
+pub mod ws;
+
+pub use error::SocksError;
+pub use protocol::{Address, SocksRequest, UDPAssociateHeader};
+pub use server::{Server, ServerBuilder};
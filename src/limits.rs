@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+
+use crate::config::UdpConfig;
+
+/// 令牌桶，容量为一秒的配额
+pub struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// `rate` 为每秒的配额，0 表示不限制
+    pub fn new(rate: u32) -> Self {
+        TokenBucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// 取一个令牌，配额用完时返回 false
+    pub fn take(&mut self) -> bool {
+        if self.rate == 0.0 {
+            return true;
+        }
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// 当前存在的 UDP 关联计数，全局及按用户统计
+#[derive(Debug, Default)]
+pub struct Associations {
+    counts: Mutex<Counts>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    total: usize,
+    per_user: HashMap<String, usize>,
+}
+
+impl Associations {
+    /// 为 `owner` 占用一个名额，超出全局或该用户的上限时返回 None
+    pub fn acquire(self: &Arc<Self>, owner: &str, config: &UdpConfig) -> Option<AssociationGuard> {
+        let mut counts = self.counts.lock().unwrap();
+        if config.max_associations != 0 && counts.total >= config.max_associations {
+            return None;
+        }
+        let user_count = counts.per_user.get(owner).copied().unwrap_or(0);
+        if config.max_associations_per_user != 0 && user_count >= config.max_associations_per_user {
+            return None;
+        }
+        counts.total += 1;
+        counts.per_user.insert(owner.to_string(), user_count + 1);
+        Some(AssociationGuard {
+            associations: self.clone(),
+            owner: owner.to_string(),
+        })
+    }
+}
+
+/// 持有期间占用一个 UDP 关联名额，drop 时归还
+pub struct AssociationGuard {
+    associations: Arc<Associations>,
+    owner: String,
+}
+
+impl Drop for AssociationGuard {
+    fn drop(&mut self) {
+        let mut counts = self.associations.counts.lock().unwrap();
+        counts.total -= 1;
+        if let Some(count) = counts.per_user.get_mut(&self.owner) {
+            *count -= 1;
+            if *count == 0 {
+                counts.per_user.remove(&self.owner);
+            }
+        }
+    }
+}
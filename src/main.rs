@@ -1,29 +1,36 @@
-// src/main.rs
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::error::Error;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
+use tokio::time::timeout;
 use tracing::{Level, error, info};
 
-mod auth;
-mod consts;
-mod handler;
-mod protocol;
-mod udp;
-
-use auth::UserConfig;
-
-use crate::auth::User;
+use proxy::auth::{User, UserConfig};
+use proxy::client::Client;
+use proxy::config::{self, Config, LocalConfig, TransparentConfig, TunnelConfig, WebSocketConfig};
+use proxy::protocol::{self, format_target};
+#[cfg(target_os = "linux")]
+use proxy::transparent;
+use proxy::{Server, forward, metrics, reverse, tunnel, ws};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// 监听地址
-    #[arg(short, long, default_value = "127.0.0.1")]
-    ip: String,
+    /// 配置文件 (TOML)，命令行参数会覆盖其中的同名配置
+    #[arg(short, long)]
+    config: Option<PathBuf>,
 
-    /// 监听端口
-    #[arg(short, long, default_value_t = 8080)]
-    port: u16,
+    /// 监听地址 [默认: 127.0.0.1]
+    #[arg(short, long)]
+    ip: Option<String>,
+
+    /// 监听端口 [默认: 8080]
+    #[arg(short, long)]
+    port: Option<u16>,
 
     /// 认证用户名 (可选)
     #[arg(short, long)]
@@ -33,9 +40,95 @@ struct Args {
     #[arg(long)]
     pass: Option<String>,
 
-    /// 超时时间
+    /// 超时时间 [默认: 5]
+    #[arg(long)]
+    timeout: Option<u8>,
+
+    /// WebSocket 监听地址 (如 0.0.0.0:8443)，在该端口上接受 WebSocket 承载的 SOCKS5
+    #[arg(long)]
+    ws_listen: Option<String>,
+
+    /// WebSocket 升级路径 [默认: /]
+    #[arg(long)]
+    ws_path: Option<String>,
+
+    /// 本地客户端模式：把每个 SOCKS5 会话经 WebSocket 转发到该服务端 (ws:// 或 wss://)
+    #[arg(long, conflicts_with = "remote")]
+    ws_server: Option<String>,
+
+    /// 加密隧道监听地址 (远端节点，如 0.0.0.0:9000)，需要配合 key 使用
+    #[arg(long)]
+    tunnel_listen: Option<String>,
+
+    /// 本地节点模式：把每个 CONNECT / UDP ASSOCIATE 经加密隧道转发到该远端节点 (host:port)
+    #[arg(long)]
+    remote: Option<String>,
+
+    /// 加密隧道的预共享密钥
+    #[arg(long)]
+    key: Option<String>,
+
+    /// 透明代理监听地址 (Linux，iptables REDIRECT)，TPROXY 模式需在配置文件中设置
+    #[arg(long)]
+    transparent_listen: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 作为 SOCKS5 客户端经任意代理测试连通性、解析和 UDP
+    Client(ClientArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ClientArgs {
+    /// 代理服务器地址 (host:port)
+    #[arg(short, long, default_value = "127.0.0.1:8080")]
+    server: String,
+
+    /// 认证用户名 (可选)
+    #[arg(short, long)]
+    user: Option<String>,
+
+    /// 认证密码 (必须配合 user 使用)
+    #[arg(long)]
+    pass: Option<String>,
+
+    /// 每一步等待应答的超时时间 (秒)
     #[arg(long, default_value_t = 5)]
-    timeout: u8,
+    timeout: u64,
+
+    #[command(subcommand)]
+    action: ClientAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum ClientAction {
+    /// CONNECT 目标 (host:port)，可发送一段数据并打印收到的应答
+    Connect {
+        target: String,
+        /// 连接后发送的数据，支持 \r \n \t 转义
+        #[arg(long)]
+        send: Option<String>,
+    },
+    /// BIND，打印代理的监听地址并等待目标连入
+    Bind { target: String },
+    /// 经 UDP ASSOCIATE 向目标 (host:port) 发送探测报文并等待回包
+    Udp {
+        target: String,
+        /// 报文内容
+        #[arg(long, default_value = "ping")]
+        data: String,
+        /// 发送次数
+        #[arg(long, default_value_t = 1)]
+        count: u32,
+    },
+    /// 由代理解析域名 (RESOLVE 扩展命令)
+    Resolve { name: String },
+    /// 由代理反向解析 IP (RESOLVE_PTR 扩展命令)
+    ResolvePtr { ip: IpAddr },
 }
 
 #[tokio::main]
@@ -48,43 +141,337 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let args = Args::parse();
 
-    let mut config = UserConfig {
-        user: None,
-        timeout: args.timeout,
+    if let Some(Command::Client(client)) = args.command {
+        if let Err(e) = run_client(client).await {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let mut file_config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
     };
-    let timeout = args.timeout;
+
+    let timeout = args.timeout.or(file_config.timeout).unwrap_or(5);
+    let mut users = std::mem::take(&mut file_config.users);
     if let Some(user) = args.user {
         if let Some(pass) = args.pass {
-            info!("use auth,user:{}", user);
-            config = UserConfig {
-                user: Some(User {
-                    username: user,
-                    password: pass,
-                }),
-                timeout,
-            };
+            users = vec![User {
+                username: user,
+                password: pass,
+                udp: Default::default(),
+            }];
         } else {
             error!("no password");
             std::process::exit(1);
         }
-    } else {
+    }
+    if users.is_empty() {
         info!("running in No_auth");
+    } else {
+        for user in &users {
+            info!("use auth,user:{}", user.username);
+        }
     }
 
-    let config = std::sync::Arc::new(config);
+    let mut builder = Server::builder()
+        .users(users)
+        .timeout(timeout)
+        .splice(file_config.splice.unwrap_or(true))
+        .timeouts(file_config.timeouts.clone())
+        .udp(file_config.udp.clone())
+        .rules(file_config.rules.clone())
+        .dns(file_config.dns.clone());
+    if let Some(proxy_protocol) = file_config.proxy_protocol.take() {
+        builder = builder.proxy_protocol(proxy_protocol);
+    }
+    let server = builder.build().await?;
+    let config = server.config().clone();
 
-    let addr = format!("{}:{}", args.ip, args.port);
-    let listener = TcpListener::bind(&addr).await?;
-    info!("SOCKS5 Server running on {}", addr);
+    // 反向隧道代理端模式：只向服务端注册服务，不开 SOCKS5 端口
+    if let Some(agent) = file_config.agent {
+        let idle = Duration::from_secs(config.timeouts.idle);
+        return reverse::run_agent(agent, timeout, idle).await;
+    }
+
+    let ip = args
+        .ip
+        .or(file_config.ip)
+        .unwrap_or("127.0.0.1".to_string());
+    let port = args.port.or(file_config.port).unwrap_or(8080);
+    let listener = TcpListener::bind(format!("{}:{}", ip, port)).await?;
+
+    // 本地客户端模式：连接全部转发给服务端/远端节点
+    // --ws-server 与 --remote 互斥 (由 clap 检查)，最多只有一个
+    let local = match args.ws_server.or(args.remote) {
+        Some(server) => Some(LocalConfig { server, key: None }),
+        None => file_config.local,
+    };
+    if let Some(mut local) = local {
+        if args.key.is_some() {
+            local.key = args.key;
+        }
+        if local.is_websocket() {
+            return ws::run_local(listener, local.server, config).await;
+        }
+        let Some(key) = local.key else {
+            error!("tunnel to {} requires a key", local.server);
+            std::process::exit(1);
+        };
+        return tunnel::run_local(listener, local.server, key, config).await;
+    }
 
-    loop {
-        let (socket, addr) = listener.accept().await?;
-        let config_clone = config.clone();
+    let websocket = match args.ws_listen {
+        Some(listen) => Some(WebSocketConfig {
+            listen,
+            path: config::default_ws_path(),
+            udp: Default::default(),
+        }),
+        None => file_config.websocket,
+    };
+    if let Some(mut websocket) = websocket {
+        if let Some(path) = args.ws_path {
+            websocket.path = path;
+        }
+        let ws_listener = TcpListener::bind(&websocket.listen).await?;
+        let config_clone = Arc::new(UserConfig {
+            udp: config.udp.with_override(&websocket.udp),
+            ..(*config).clone()
+        });
+        tokio::spawn(async move {
+            if let Err(e) = ws::serve(ws_listener, websocket.path, config_clone).await {
+                error!("WebSocket listener stopped: {}", e);
+            }
+        });
+    }
 
+    let tunnel = match args.tunnel_listen {
+        Some(listen) => Some(TunnelConfig {
+            listen,
+            key: args.key.clone().unwrap_or_default(),
+            udp: Default::default(),
+        }),
+        None => file_config.tunnel,
+    };
+    if let Some(mut tunnel) = tunnel {
+        if let Some(key) = args.key {
+            tunnel.key = key;
+        }
+        if tunnel.key.is_empty() {
+            error!("tunnel listener requires a key");
+            std::process::exit(1);
+        }
+        let tunnel_listener = TcpListener::bind(&tunnel.listen).await?;
+        let config_clone = Arc::new(UserConfig {
+            udp: config.udp.with_override(&tunnel.udp),
+            ..(*config).clone()
+        });
         tokio::spawn(async move {
-            if let Err(e) = handler::process(socket, config_clone.as_ref()).await {
-                error!("[Error] from {:?} : {}", addr, e);
+            if let Err(e) = tunnel::serve(tunnel_listener, tunnel.key, config_clone).await {
+                error!("Tunnel listener stopped: {}", e);
             }
         });
     }
+
+    let transparent = match args.transparent_listen {
+        Some(listen) => Some(TransparentConfig {
+            listen,
+            mode: Default::default(),
+            udp: false,
+        }),
+        None => file_config.transparent,
+    };
+    if let Some(transparent) = transparent {
+        #[cfg(target_os = "linux")]
+        {
+            use config::TransparentMode;
+
+            if transparent.udp && transparent.mode != TransparentMode::Tproxy {
+                error!("transparent UDP requires mode = \"tproxy\"");
+                std::process::exit(1);
+            }
+            let transparent_listener =
+                transparent::bind(&transparent.listen, transparent.mode).await?;
+            let config_clone = config.clone();
+            tokio::spawn(async move {
+                if let Err(e) =
+                    transparent::serve(transparent_listener, transparent.mode, config_clone).await
+                {
+                    error!("Transparent listener stopped: {}", e);
+                }
+            });
+            if transparent.udp {
+                let udp_socket = transparent::bind_udp(&transparent.listen).await?;
+                let config_clone = config.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = transparent::serve_udp(udp_socket, config_clone).await {
+                        error!("Transparent UDP listener stopped: {}", e);
+                    }
+                });
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            error!("transparent proxy on {} requires Linux", transparent.listen);
+            std::process::exit(1);
+        }
+    }
+
+    for forward in std::mem::take(&mut file_config.forward) {
+        forward::start(forward, config.clone()).await?;
+    }
+
+    tokio::spawn(metrics::report());
+
+    server.serve(listener).await
+}
+
+/// `proxy client`：结果打印到标准输出，失败时返回错误 (退出码非 0)
+async fn run_client(args: ClientArgs) -> Result<(), Box<dyn Error>> {
+    let mut client = Client::new(&args.server);
+    match (args.user, args.pass) {
+        (Some(user), Some(pass)) => client = client.auth(user, pass),
+        (None, None) => {}
+        _ => return Err("user and pass must be given together".into()),
+    }
+    let wait = Duration::from_secs(args.timeout);
+    let parse = |target: &str| {
+        protocol::parse_target(target).ok_or_else(|| format!("invalid target: {}", target))
+    };
+
+    match args.action {
+        ClientAction::Connect { target, send } => {
+            let (address, port) = parse(&target)?;
+            let start = Instant::now();
+            let (mut stream, (bound, bound_port)) = timeout(wait, client.connect(address, port))
+                .await
+                .map_err(|_| "CONNECT timed out")??;
+            println!(
+                "CONNECT {} ok in {:?} (bound {})",
+                target,
+                start.elapsed(),
+                format_target(&bound, bound_port)
+            );
+            if let Some(data) = send {
+                stream.write_all(unescape(&data).as_bytes()).await?;
+                let mut response = Vec::new();
+                // 读到 EOF 或超时为止
+                let _ = timeout(wait, stream.read_to_end(&mut response)).await;
+                println!("{}", String::from_utf8_lossy(&response));
+            }
+        }
+        ClientAction::Bind { target } => {
+            let (address, port) = parse(&target)?;
+            let bind = timeout(wait, client.bind(address, port))
+                .await
+                .map_err(|_| "BIND timed out")??;
+            let (bound, bound_port) = bind.bound();
+            println!("BIND listening on {}", format_target(bound, *bound_port));
+            let (_, (peer, peer_port)) = bind.accept().await?;
+            println!("BIND accepted {}", format_target(&peer, peer_port));
+        }
+        ClientAction::Udp {
+            target,
+            data,
+            count,
+        } => {
+            let (address, port) = parse(&target)?;
+            let association = timeout(wait, client.udp_associate())
+                .await
+                .map_err(|_| "UDP ASSOCIATE timed out")??;
+            println!("UDP ASSOCIATE relay {}", association.relay());
+
+            let mut buf = vec![0u8; 65535];
+            let mut received = 0;
+            for _ in 0..count {
+                let start = Instant::now();
+                association
+                    .send_to(data.as_bytes(), address.clone(), port)
+                    .await?;
+                match timeout(wait, association.recv_from(&mut buf)).await {
+                    Ok(Ok((len, (src, src_port)))) => {
+                        received += 1;
+                        println!(
+                            "reply from {} in {:?}: {}",
+                            format_target(&src, src_port),
+                            start.elapsed(),
+                            String::from_utf8_lossy(&buf[..len])
+                        );
+                    }
+                    Ok(Err(e)) => println!("receive failed: {}", e),
+                    Err(_) => println!("no reply within {:?}", wait),
+                }
+            }
+            println!("{}/{} replies", received, count);
+            if received == 0 {
+                return Err("no UDP replies".into());
+            }
+        }
+        ClientAction::Resolve { name } => {
+            let address = timeout(wait, client.resolve(&name))
+                .await
+                .map_err(|_| "RESOLVE timed out")??;
+            println!(
+                "{} -> {}",
+                name,
+                format_target(&address, 0).trim_end_matches(":0")
+            );
+        }
+        ClientAction::ResolvePtr { ip } => {
+            let address = timeout(wait, client.resolve_ptr(ip))
+                .await
+                .map_err(|_| "RESOLVE_PTR timed out")??;
+            println!(
+                "{} -> {}",
+                ip,
+                format_target(&address, 0).trim_end_matches(":0")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// 命令行里无法直接输入控制字符，支持 `\r` `\n` `\t` `\\`
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unescape;
+
+    #[test]
+    fn unescape_control_chars() {
+        assert_eq!(
+            unescape(r"GET / HTTP/1.0\r\n\r\n"),
+            "GET / HTTP/1.0\r\n\r\n"
+        );
+        assert_eq!(unescape(r"a\tb"), "a\tb");
+        assert_eq!(unescape(r"C:\\dir"), "C:\\dir");
+        // 转义后的反斜杠不会和后面的字符组成新的转义
+        assert_eq!(unescape(r"\\n"), "\\n");
+        // 未知转义和结尾的反斜杠原样保留
+        assert_eq!(unescape(r"\x\"), "\\x\\");
+    }
 }
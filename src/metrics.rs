@@ -0,0 +1,122 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::info;
+
+use crate::error::SocksError;
+
+/// 丢弃计数输出到日志的间隔
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// UDP 报文 (或关联) 被丢弃的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpDrop {
+    /// 来自客户端以外的地址
+    NonClient,
+    /// 回包未通过 NAT 过滤策略
+    Filtered,
+    /// 超出关联的速率限制
+    RateLimited,
+    /// 超出关联的目标数上限
+    Destinations,
+    /// 负载超过 max_datagram_size
+    Oversized,
+    /// 无法解析的 SOCKS5 UDP 头
+    Malformed,
+    /// 被丢弃的分片或未完成重组的分片序列
+    Fragment,
+    /// 超出关联数上限而被拒绝的 UDP ASSOCIATE
+    Associations,
+    /// 目标域名在黑名单中
+    Blocked,
+    /// 拦截的 DNS 查询在途数超出关联的上限
+    DnsQueue,
+}
+
+impl UdpDrop {
+    const ALL: [UdpDrop; 10] = [
+        UdpDrop::NonClient,
+        UdpDrop::Filtered,
+        UdpDrop::RateLimited,
+        UdpDrop::Destinations,
+        UdpDrop::Oversized,
+        UdpDrop::Malformed,
+        UdpDrop::Fragment,
+        UdpDrop::Associations,
+        UdpDrop::Blocked,
+        UdpDrop::DnsQueue,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            UdpDrop::NonClient => "non_client",
+            UdpDrop::Filtered => "filtered",
+            UdpDrop::RateLimited => "rate_limited",
+            UdpDrop::Destinations => "destinations",
+            UdpDrop::Oversized => "oversized",
+            UdpDrop::Malformed => "malformed",
+            UdpDrop::Fragment => "fragment",
+            UdpDrop::Associations => "associations",
+            UdpDrop::Blocked => "blocked",
+            UdpDrop::DnsQueue => "dns_queue",
+        }
+    }
+}
+
+static UDP_DROPS: [AtomicU64; UdpDrop::ALL.len()] =
+    [const { AtomicU64::new(0) }; UdpDrop::ALL.len()];
+
+pub fn udp_drop(reason: UdpDrop) {
+    UDP_DROPS[reason as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// 各原因的累计丢弃数
+pub fn udp_drops() -> impl Iterator<Item = (UdpDrop, u64)> {
+    UdpDrop::ALL
+        .into_iter()
+        .map(|reason| (reason, UDP_DROPS[reason as usize].load(Ordering::Relaxed)))
+}
+
+static SESSION_ERRORS: [AtomicU64; SocksError::LABELS.len()] =
+    [const { AtomicU64::new(0) }; SocksError::LABELS.len()];
+
+/// 按失败类型统计以错误结束的 SOCKS5 会话
+pub fn session_error(e: &SocksError) {
+    SESSION_ERRORS[e.index()].fetch_add(1, Ordering::Relaxed);
+}
+
+/// 各失败类型 (标签) 的累计会话数
+pub fn session_errors() -> impl Iterator<Item = (&'static str, u64)> {
+    SocksError::LABELS
+        .into_iter()
+        .zip(SESSION_ERRORS.iter().map(|n| n.load(Ordering::Relaxed)))
+}
+
+/// 定期把有变化的丢弃计数和会话失败计数输出到日志
+pub async fn report() {
+    let mut last_drops: Vec<_> = udp_drops().map(|(reason, _)| (reason.name(), 0)).collect();
+    let mut last_errors: Vec<_> = session_errors().map(|(label, _)| (label, 0)).collect();
+    let mut interval = tokio::time::interval(REPORT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let drops: Vec<_> = udp_drops().map(|(reason, n)| (reason.name(), n)).collect();
+        if drops != last_drops {
+            info!("udp drops: {}", summary(&drops));
+            last_drops = drops;
+        }
+        let errors: Vec<_> = session_errors().collect();
+        if errors != last_errors {
+            info!("session errors: {}", summary(&errors));
+            last_errors = errors;
+        }
+    }
+}
+
+/// `name=n` 列表，省略为 0 的项
+fn summary(counts: &[(&str, u64)]) -> String {
+    counts
+        .iter()
+        .filter(|(_, n)| *n > 0)
+        .map(|(name, n)| format!("{}={}", name, n))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
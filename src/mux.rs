@@ -0,0 +1,520 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tracing::debug;
+
+use crate::transport::Stream;
+
+// 多路复用帧
+// +------+-----------+--------+----------+
+// | TYPE | STREAM ID | LENGTH |   DATA   |
+// +------+-----------+--------+----------+
+// |  1   |     4     |   4    | Variable |
+// +------+-----------+--------+----------+
+//
+// - OPEN:   打开新流
+// - DATA:   流数据，LENGTH 为数据长度
+// - WINDOW: 流量控制，LENGTH 为对端新增的可发送字节数 (无 DATA)
+// - FIN:    半关闭，发送方不会再发数据，另一方向仍可继续
+// - RST:    异常终止
+// - PING / PONG: 保活，STREAM ID 为 0，LENGTH 为回显的序号
+//
+// 主动打开流的一方 (本地节点) 使用奇数 ID，另一方使用偶数 ID
+
+const FRAME_OPEN: u8 = 0x00;
+const FRAME_DATA: u8 = 0x01;
+const FRAME_WINDOW: u8 = 0x02;
+const FRAME_FIN: u8 = 0x03;
+const FRAME_RST: u8 = 0x04;
+const FRAME_PING: u8 = 0x05;
+const FRAME_PONG: u8 = 0x06;
+
+const HEADER_LEN: usize = 9;
+const MAX_FRAME: usize = 16 * 1024;
+/// 每个流的初始接收窗口
+const INITIAL_WINDOW: u32 = 1024 * 1024;
+/// 同时存在的流数上限，超出时对端新打开的流被 RST
+const MAX_STREAMS: usize = 4096;
+
+struct Frame {
+    kind: u8,
+    stream_id: u32,
+    length: u32,
+    data: Vec<u8>,
+}
+
+impl Frame {
+    fn control(kind: u8, stream_id: u32, length: u32) -> Self {
+        Frame {
+            kind,
+            stream_id,
+            length,
+            data: Vec::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct StreamState {
+    // 读方向
+    recv_buf: VecDeque<Vec<u8>>,
+    recv_pos: usize,
+    recv_window: u32, // 对端还可以发送的字节数，超出即违反流量控制
+    read_waker: Option<Waker>,
+    remote_fin: bool,
+    reset: bool,
+    // 写方向
+    send_window: u32,
+    write_waker: Option<Waker>,
+}
+
+impl StreamState {
+    fn wake_all(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+type Shared = Arc<Mutex<StreamState>>;
+
+struct Inner {
+    tx: mpsc::UnboundedSender<Frame>,
+    streams: Mutex<HashMap<u32, Shared>>,
+    next_id: AtomicU32,
+    client: bool, // 本端使用奇数 ID
+    closed: AtomicBool,
+}
+
+impl Inner {
+    fn send(&self, frame: Frame) {
+        let _ = self.tx.send(frame);
+    }
+
+    fn new_stream(self: &Arc<Self>, id: u32) -> MuxStream {
+        let shared = Arc::new(Mutex::new(StreamState {
+            send_window: INITIAL_WINDOW,
+            recv_window: INITIAL_WINDOW,
+            ..Default::default()
+        }));
+        self.streams.lock().unwrap().insert(id, shared.clone());
+        self.stream(id, shared)
+    }
+
+    fn stream(self: &Arc<Self>, id: u32, shared: Shared) -> MuxStream {
+        MuxStream {
+            id,
+            shared,
+            session: self.clone(),
+            consumed: 0,
+            local_fin: false,
+        }
+    }
+
+    fn get(&self, id: u32) -> Option<Shared> {
+        self.streams.lock().unwrap().get(&id).cloned()
+    }
+
+    /// 登记对端打开的流；ID 属于本端、为 0 或已在使用时是协议错误，流数超限时返回 None
+    fn accept_stream(self: &Arc<Self>, id: u32) -> io::Result<Option<MuxStream>> {
+        let peer_odd = !self.client;
+        if id == 0 || (id % 2 == 1) != peer_odd {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("mux OPEN with stream id {} from the wrong side", id),
+            ));
+        }
+        let mut streams = self.streams.lock().unwrap();
+        if streams.contains_key(&id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("mux OPEN for stream {} already in use", id),
+            ));
+        }
+        if streams.len() >= MAX_STREAMS {
+            return Ok(None);
+        }
+        let shared = Arc::new(Mutex::new(StreamState {
+            send_window: INITIAL_WINDOW,
+            recv_window: INITIAL_WINDOW,
+            ..Default::default()
+        }));
+        streams.insert(id, shared.clone());
+        drop(streams);
+        Ok(Some(self.stream(id, shared)))
+    }
+
+    /// 本端重置流：移出会话、唤醒读写方并通知对端
+    fn reset(&self, id: u32) {
+        if let Some(shared) = self.streams.lock().unwrap().remove(&id) {
+            let mut state = shared.lock().unwrap();
+            state.reset = true;
+            state.wake_all();
+        }
+        self.send(Frame::control(FRAME_RST, id, 0));
+    }
+
+    /// 底层连接断开：所有流都视为被重置
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        for (_, shared) in self.streams.lock().unwrap().drain() {
+            let mut state = shared.lock().unwrap();
+            state.reset = true;
+            state.wake_all();
+        }
+    }
+}
+
+/// 一条底层连接上的多路复用会话
+#[derive(Clone)]
+pub struct Session {
+    inner: Arc<Inner>,
+}
+
+/// 对端打开的流
+pub struct Incoming {
+    rx: mpsc::UnboundedReceiver<MuxStream>,
+}
+
+impl Incoming {
+    pub async fn accept(&mut self) -> Option<MuxStream> {
+        self.rx.recv().await
+    }
+}
+
+impl Session {
+    /// 在 `io` 上建立会话并启动读写任务
+    ///
+    /// `client` 决定本端分配奇数还是偶数流 ID；每隔 `keepalive` 发送一次 PING，
+    /// 超过三个周期没有收到任何帧则认为连接已断开
+    pub fn new<T>(io: T, client: bool, keepalive: Duration) -> (Session, Incoming)
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+        let inner = Arc::new(Inner {
+            tx,
+            streams: Mutex::new(HashMap::new()),
+            next_id: AtomicU32::new(if client { 1 } else { 2 }),
+            client,
+            closed: AtomicBool::new(false),
+        });
+
+        let (reader, writer) = tokio::io::split(io);
+        tokio::spawn(write_loop(writer, rx));
+        tokio::spawn(read_loop(reader, inner.clone(), incoming_tx, keepalive * 3));
+        tokio::spawn(keepalive_loop(Arc::downgrade(&inner), keepalive));
+
+        (Session { inner }, Incoming { rx: incoming_rx })
+    }
+
+    /// 打开一个新的流
+    pub fn open(&self) -> io::Result<MuxStream> {
+        if self.is_closed() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "mux session closed",
+            ));
+        }
+        // ID 用尽后不能回绕去复用旧 ID：标记会话关闭，让调用方重新建立连接，
+        // 已打开的流不受影响
+        let next = self
+            .inner
+            .next_id
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |id| id.checked_add(2));
+        let Ok(id) = next else {
+            self.inner.closed.store(true, Ordering::SeqCst);
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "mux stream ids exhausted",
+            ));
+        };
+        let stream = self.inner.new_stream(id);
+        self.inner.send(Frame::control(FRAME_OPEN, id, 0));
+        Ok(stream)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::SeqCst)
+    }
+}
+
+async fn write_loop<W>(mut writer: W, mut rx: mpsc::UnboundedReceiver<Frame>)
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = Vec::with_capacity(HEADER_LEN + MAX_FRAME);
+    while let Some(frame) = rx.recv().await {
+        // 尽量把排队的帧合并成一次写入
+        let mut next = Some(frame);
+        while let Some(frame) = next {
+            buf.push(frame.kind);
+            buf.extend_from_slice(&frame.stream_id.to_be_bytes());
+            buf.extend_from_slice(&frame.length.to_be_bytes());
+            buf.extend_from_slice(&frame.data);
+            next = if buf.len() < MAX_FRAME {
+                rx.try_recv().ok()
+            } else {
+                None
+            };
+        }
+        if let Err(e) = async {
+            writer.write_all(&buf).await?;
+            writer.flush().await
+        }
+        .await
+        {
+            debug!("mux write error: {}", e);
+            break;
+        }
+        buf.clear();
+    }
+    let _ = writer.shutdown().await;
+}
+
+async fn read_loop<R>(
+    mut reader: R,
+    inner: Arc<Inner>,
+    incoming: mpsc::UnboundedSender<MuxStream>,
+    idle: Duration,
+) where
+    R: AsyncRead + Unpin,
+{
+    let res: io::Result<()> = async {
+        let mut header = [0u8; HEADER_LEN];
+        loop {
+            match timeout(idle, reader.read_exact(&mut header)).await {
+                Ok(res) => res?,
+                Err(_) => return Err(io::ErrorKind::TimedOut.into()),
+            };
+            let kind = header[0];
+            let stream_id = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+            let length = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+
+            match kind {
+                FRAME_OPEN => match inner.accept_stream(stream_id)? {
+                    Some(stream) => {
+                        if incoming.send(stream).is_err() {
+                            // 本端不接受对端打开的流，MuxStream 的 Drop 会回复 RST
+                            debug!("mux stream {} refused", stream_id);
+                        }
+                    }
+                    None => {
+                        debug!("mux stream {} refused: too many streams", stream_id);
+                        inner.send(Frame::control(FRAME_RST, stream_id, 0));
+                    }
+                },
+                FRAME_DATA => {
+                    if length as usize > MAX_FRAME {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "mux frame too large",
+                        ));
+                    }
+                    let mut data = vec![0u8; length as usize];
+                    reader.read_exact(&mut data).await?;
+                    let Some(shared) = inner.get(stream_id) else {
+                        continue;
+                    };
+                    let mut state = shared.lock().unwrap();
+                    if length > state.recv_window {
+                        // 对端无视 WINDOW 继续发送，重置该流而不是无限缓存
+                        drop(state);
+                        debug!("mux stream {} exceeded its receive window", stream_id);
+                        inner.reset(stream_id);
+                        continue;
+                    }
+                    state.recv_window -= length;
+                    state.recv_buf.push_back(data);
+                    if let Some(waker) = state.read_waker.take() {
+                        waker.wake();
+                    }
+                }
+                FRAME_WINDOW => {
+                    if let Some(shared) = inner.get(stream_id) {
+                        let mut state = shared.lock().unwrap();
+                        state.send_window = state.send_window.saturating_add(length);
+                        if let Some(waker) = state.write_waker.take() {
+                            waker.wake();
+                        }
+                    }
+                }
+                FRAME_FIN => {
+                    if let Some(shared) = inner.get(stream_id) {
+                        let mut state = shared.lock().unwrap();
+                        state.remote_fin = true;
+                        if let Some(waker) = state.read_waker.take() {
+                            waker.wake();
+                        }
+                    }
+                }
+                FRAME_RST => {
+                    if let Some(shared) = inner.streams.lock().unwrap().remove(&stream_id) {
+                        let mut state = shared.lock().unwrap();
+                        state.reset = true;
+                        state.wake_all();
+                    }
+                }
+                FRAME_PING => inner.send(Frame::control(FRAME_PONG, 0, length)),
+                FRAME_PONG => {}
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown mux frame type: 0x{:02x}", kind),
+                    ));
+                }
+            }
+        }
+    }
+    .await;
+
+    if let Err(e) = res {
+        debug!("mux session closed: {}", e);
+    }
+    inner.close();
+}
+
+async fn keepalive_loop(inner: std::sync::Weak<Inner>, interval: Duration) {
+    let mut seq = 0u32;
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        if inner.closed.load(Ordering::SeqCst) {
+            return;
+        }
+        seq = seq.wrapping_add(1);
+        inner.send(Frame::control(FRAME_PING, 0, seq));
+    }
+}
+
+/// 会话中的一个逻辑流
+///
+/// shutdown 只关闭写方向 (发送 FIN)，读方向直到对端 FIN 才返回 EOF；
+/// 未正常结束就被丢弃时向对端发送 RST
+pub struct MuxStream {
+    id: u32,
+    shared: Shared,
+    session: Arc<Inner>,
+    // 已读取但尚未归还给对端的窗口
+    consumed: u32,
+    local_fin: bool,
+}
+
+impl AsyncRead for MuxStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let mut state = this.shared.lock().unwrap();
+
+        let mut n = 0;
+        while buf.remaining() > 0 {
+            let pos = state.recv_pos;
+            let Some(chunk) = state.recv_buf.front() else {
+                break;
+            };
+            let len = (chunk.len() - pos).min(buf.remaining());
+            buf.put_slice(&chunk[pos..pos + len]);
+            n += len;
+            if pos + len == chunk.len() {
+                state.recv_buf.pop_front();
+                state.recv_pos = 0;
+            } else {
+                state.recv_pos += len;
+            }
+        }
+
+        if n > 0 {
+            this.consumed += n as u32;
+            if this.consumed >= INITIAL_WINDOW / 2 {
+                state.recv_window += this.consumed;
+                drop(state);
+                this.session
+                    .send(Frame::control(FRAME_WINDOW, this.id, this.consumed));
+                this.consumed = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+
+        if state.reset {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+        if state.remote_fin {
+            return Poll::Ready(Ok(()));
+        }
+        state.read_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for MuxStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.lock().unwrap();
+        if state.reset {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+        if self.local_fin {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if state.send_window == 0 {
+            state.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let n = buf.len().min(MAX_FRAME).min(state.send_window as usize);
+        state.send_window -= n as u32;
+        drop(state);
+
+        self.session.send(Frame {
+            kind: FRAME_DATA,
+            stream_id: self.id,
+            length: n as u32,
+            data: buf[..n].to_vec(),
+        });
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.local_fin {
+            self.local_fin = true;
+            self.session.send(Frame::control(FRAME_FIN, self.id, 0));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for MuxStream {
+    fn drop(&mut self) {
+        self.session.streams.lock().unwrap().remove(&self.id);
+        let state = self.shared.lock().unwrap();
+        let finished = self.local_fin && state.remote_fin;
+        if !state.reset && !finished {
+            self.session.send(Frame::control(FRAME_RST, self.id, 0));
+        }
+    }
+}
+
+impl Stream for MuxStream {}
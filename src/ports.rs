@@ -0,0 +1,288 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::batch::{self, BATCH, RecvBatch};
+use crate::config::UdpConfig;
+use crate::metrics::{self, UdpDrop};
+use crate::udp;
+
+/// 共享端口上每个关联排队等待处理的报文上限
+const SHARED_QUEUE: usize = 256;
+
+/// 面向客户端的 UDP 端口分配
+///
+/// - 默认：每个关联绑定一个随机端口
+/// - `port_range`：在范围内依次尝试绑定，全部占用时报错
+/// - `shared_port`：所有关联共用一个端口，按客户端地址分发报文
+#[derive(Debug)]
+pub struct UdpPorts {
+    range: Option<(u16, u16)>,
+    next: Mutex<u16>,
+    shared: Option<Arc<Shared>>,
+}
+
+impl UdpPorts {
+    pub async fn new(config: &UdpConfig) -> io::Result<Self> {
+        if let Some((min, max)) = config.port_range
+            && (min == 0 || min > max)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid udp port_range [{}, {}]", min, max),
+            ));
+        }
+
+        let shared = match config.shared_port {
+            Some(port) => Some(Shared::bind(port).await?),
+            None => None,
+        };
+        Ok(UdpPorts {
+            range: config.port_range,
+            next: Mutex::new(config.port_range.map_or(0, |(min, _)| min)),
+            shared,
+        })
+    }
+
+    /// 为期望的客户端地址 (见 [`udp::expected_client`]) 分配面向客户端的 socket
+    pub async fn bind(&self, client: SocketAddr) -> io::Result<RelaySocket> {
+        if let Some(shared) = &self.shared {
+            return Ok(RelaySocket::Shared(shared.register(client)));
+        }
+        let Some((min, max)) = self.range else {
+            let socket = udp::bind_relay(client.ip()).await?;
+            batch::enable_gro(&socket);
+            return Ok(RelaySocket::Dedicated(socket));
+        };
+
+        let ip = unspecified(client.ip());
+        let count = (max - min) as u32 + 1;
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            let start = *next;
+            *next = if start >= max { min } else { start + 1 };
+            start
+        };
+        for i in 0..count {
+            let port = min as u32 + ((start - min) as u32 + i) % count;
+            match UdpSocket::bind(SocketAddr::new(ip, port as u16)).await {
+                Ok(socket) => {
+                    batch::enable_gro(&socket);
+                    return Ok(RelaySocket::Dedicated(socket));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("udp port range [{}, {}] exhausted", min, max),
+        ))
+    }
+}
+
+/// 与客户端地址同族的全 0 地址，双栈下 IPv4 映射地址按 IPv4 处理
+fn unspecified(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+}
+
+/// UDP 关联面向客户端的 socket
+pub enum RelaySocket {
+    Dedicated(UdpSocket),
+    Shared(SharedSocket),
+}
+
+impl RelaySocket {
+    /// 告诉客户端的地址：共享端口时地址族与客户端一致
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            RelaySocket::Dedicated(socket) => socket.local_addr(),
+            RelaySocket::Shared(shared) => Ok(SocketAddr::new(
+                unspecified(shared.client.ip()),
+                shared.shared.socket.local_addr()?.port(),
+            )),
+        }
+    }
+
+    /// 接收一批报文；共享端口时取出分发给该关联的已排队报文
+    pub async fn recv(&mut self) -> io::Result<RecvBatch> {
+        match self {
+            RelaySocket::Dedicated(socket) => batch::recv(socket).await,
+            RelaySocket::Shared(shared) => {
+                let Some(first) = shared.rx.recv().await else {
+                    return Err(io::Error::other("shared udp port closed"));
+                };
+                let mut packets = vec![first];
+                while packets.len() < BATCH {
+                    match shared.rx.try_recv() {
+                        Ok(packet) => packets.push(packet),
+                        Err(_) => break,
+                    }
+                }
+                Ok(RecvBatch::from_packets(packets))
+            }
+        }
+    }
+
+    /// 把若干报文发给客户端
+    pub async fn send(&self, target: SocketAddr, packets: &[Vec<u8>]) -> io::Result<()> {
+        match self {
+            RelaySocket::Dedicated(socket) => batch::send(socket, target, packets).await,
+            RelaySocket::Shared(shared) => shared.shared.send(target, packets).await,
+        }
+    }
+}
+
+/// 所有关联共用的端口
+///
+/// 声明了完整地址的关联直接按地址分发；未声明端口的关联按 IP 排队，
+/// 该 IP 上第一个未知来源的报文分给最早登记的关联，之后锁定该地址
+#[derive(Debug)]
+struct Shared {
+    socket: UdpSocket,
+    routes: Mutex<Routes>,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Routes {
+    exact: HashMap<SocketAddr, Route>,
+    pending: HashMap<IpAddr, VecDeque<Route>>,
+}
+
+#[derive(Debug, Clone)]
+struct Route {
+    id: u64,
+    tx: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+}
+
+impl Shared {
+    /// 优先绑定双栈 `[::]`，系统不支持 IPv6 时退回 `0.0.0.0`
+    async fn bind(port: u16) -> io::Result<Arc<Self>> {
+        let socket = match UdpSocket::bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port))
+            .await
+        {
+            Ok(socket) => socket,
+            Err(_) => {
+                UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)).await?
+            }
+        };
+        batch::enable_gro(&socket);
+        info!("Shared UDP relay port running on {}", socket.local_addr()?);
+
+        let shared = Arc::new(Shared {
+            socket,
+            routes: Mutex::new(Routes::default()),
+            next_id: AtomicU64::new(0),
+        });
+        tokio::spawn(shared.clone().demux());
+        Ok(shared)
+    }
+
+    fn register(self: &Arc<Self>, client: SocketAddr) -> SharedSocket {
+        let client = SocketAddr::new(client.ip().to_canonical(), client.port());
+        let (tx, rx) = mpsc::channel(SHARED_QUEUE);
+        let route = Route {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            tx,
+        };
+        let id = route.id;
+
+        let mut routes = self.routes.lock().unwrap();
+        if client.port() != 0 {
+            routes.exact.insert(client, route);
+        } else {
+            routes
+                .pending
+                .entry(client.ip())
+                .or_default()
+                .push_back(route);
+        }
+        SharedSocket {
+            shared: self.clone(),
+            client,
+            id,
+            rx,
+        }
+    }
+
+    fn route(&self, src: SocketAddr) -> Option<Route> {
+        let mut routes = self.routes.lock().unwrap();
+        if let Some(route) = routes.exact.get(&src) {
+            return Some(route.clone());
+        }
+        let queue = routes.pending.get_mut(&src.ip())?;
+        let route = queue.pop_front()?;
+        if queue.is_empty() {
+            routes.pending.remove(&src.ip());
+        }
+        debug!("lock shared udp client:{}", src);
+        routes.exact.insert(src, route.clone());
+        Some(route)
+    }
+
+    async fn demux(self: Arc<Self>) {
+        loop {
+            let batch = match batch::recv(&self.socket).await {
+                Ok(batch) => batch,
+                Err(e) => {
+                    debug!("shared udp read error: {}", e);
+                    continue;
+                }
+            };
+            for (packet, src) in batch.iter() {
+                let src = SocketAddr::new(src.ip().to_canonical(), src.port());
+                let Some(route) = self.route(src) else {
+                    debug!("drop udp packet from non-client {}", src);
+                    metrics::udp_drop(UdpDrop::NonClient);
+                    continue;
+                };
+                if route.tx.try_send((packet.to_vec(), src)).is_err() {
+                    warn!("shared udp queue full, drop packet from {}", src);
+                }
+            }
+        }
+    }
+
+    async fn send(&self, target: SocketAddr, packets: &[Vec<u8>]) -> io::Result<()> {
+        // 双栈 socket 上 IPv4 客户端要用映射地址
+        let target = match (self.socket.local_addr()?, target.ip()) {
+            (SocketAddr::V6(_), IpAddr::V4(ip)) => {
+                SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), target.port())
+            }
+            _ => target,
+        };
+        batch::send(&self.socket, target, packets).await
+    }
+
+    fn unregister(&self, id: u64) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.exact.retain(|_, route| route.id != id);
+        routes.pending.retain(|_, queue| {
+            queue.retain(|route| route.id != id);
+            !queue.is_empty()
+        });
+    }
+}
+
+/// 共享端口上的一个关联，drop 时注销
+pub struct SharedSocket {
+    shared: Arc<Shared>,
+    client: SocketAddr,
+    id: u64,
+    rx: mpsc::Receiver<(Vec<u8>, SocketAddr)>,
+}
+
+impl Drop for SharedSocket {
+    fn drop(&mut self) {
+        self.shared.unregister(self.id);
+    }
+}
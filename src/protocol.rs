@@ -1,38 +1,82 @@
-use std::error::Error;
 use std::fmt;
-use std::net::{Ipv4Addr, Ipv6Addr};
-use std::ops::Add;
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpStream;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 use crate::consts::*;
+use crate::error::SocksError;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Address {
     IpV4(Ipv4Addr),
     Domain(String),
     IpV6(Ipv6Addr),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SocksRequest {
     pub cmd: u8,
     pub address: Address,
     pub port: u16,
 }
 
+/// 格式化为 `host:port`，IPv6 加方括号，可直接用于 connect/send_to
+fn fmt_target(f: &mut fmt::Formatter<'_>, address: &Address, port: u16) -> fmt::Result {
+    match address {
+        Address::IpV4(ip) => write!(f, "{}:{}", ip, port),
+        Address::Domain(domain) => write!(f, "{}:{}", domain, port),
+        Address::IpV6(ip) => write!(f, "[{}]:{}", ip, port),
+    }
+}
+
+impl From<IpAddr> for Address {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => Address::IpV4(ip),
+            IpAddr::V6(ip) => Address::IpV6(ip),
+        }
+    }
+}
+
+impl Address {
+    /// IP 地址直接转换，域名返回 None (需要解析)
+    pub fn to_socket_addr(&self, port: u16) -> Option<SocketAddr> {
+        match self {
+            Address::IpV4(ip) => Some(SocketAddr::from((*ip, port))),
+            Address::IpV6(ip) => Some(SocketAddr::from((*ip, port))),
+            Address::Domain(_) => None,
+        }
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) {
+        match self {
+            Address::IpV4(ip) => {
+                buf.push(ATYP_IPV4);
+                buf.extend_from_slice(&ip.octets());
+            }
+            Address::Domain(domain) => {
+                buf.push(ATYP_DOMAIN);
+                buf.push(domain.len() as u8);
+                buf.extend_from_slice(domain.as_bytes());
+            }
+            Address::IpV6(ip) => {
+                buf.push(ATYP_IPV6);
+                buf.extend_from_slice(&ip.octets());
+            }
+        }
+    }
+}
+
 impl fmt::Display for SocksRequest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self.address {
-            Address::IpV4(ip) => write!(f, "{}:{}", ip, self.port),
-            Address::Domain(domain) => write!(f, "{}:{}", domain, self.port),
-            Address::IpV6(ip) => write!(f, "[{}],{}", ip, self.port),
-        }
+        fmt_target(f, &self.address, self.port)
     }
 }
 
 impl SocksRequest {
-    pub async fn read_from(socket: &mut TcpStream) -> Result<Self, Box<dyn Error>> {
+    pub async fn read_from<R>(socket: &mut R) -> Result<Self, SocksError>
+    where
+        R: AsyncRead + Unpin,
+    {
         let mut head = [0u8; 4];
         socket.read_exact(&mut head).await?;
 
@@ -41,7 +85,7 @@ impl SocksRequest {
         let atyp = head[3];
 
         if ver != SOCKS_VERSION {
-            return Err(format!("unsupport socks version: 0x{:02x}", ver).into());
+            return Err(SocksError::UnsupportedVersion(ver));
         }
 
         let address = match atyp {
@@ -61,7 +105,8 @@ impl SocksRequest {
                 socket.read_exact(&mut buf).await?;
 
                 // 转换成 String
-                let domain = String::from_utf8(buf).map_err(|_| "wrong domain")?;
+                let domain = String::from_utf8(buf)
+                    .map_err(|_| SocksError::Protocol("wrong domain".into()))?;
                 Address::Domain(domain)
             }
             ATYP_IPV6 => {
@@ -69,7 +114,7 @@ impl SocksRequest {
                 socket.read_exact(&mut buf).await?;
                 Address::IpV6(Ipv6Addr::from(buf))
             }
-            _ => return Err(format!("unknow address type: 0x{:02x}", atyp).into()),
+            _ => return Err(SocksError::AddressTypeNotSupported(atyp)),
         };
 
         let mut port_buf = [0u8; 2];
@@ -78,6 +123,49 @@ impl SocksRequest {
 
         Ok(SocksRequest { cmd, address, port })
     }
+
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&[SOCKS_VERSION, self.cmd, 0x00]);
+        self.address.write(buf);
+        buf.extend_from_slice(&self.port.to_be_bytes());
+    }
+}
+
+/// 格式化为 `host:port`，IPv6 加方括号
+pub fn format_target(address: &Address, port: u16) -> String {
+    struct Target<'a>(&'a Address, u16);
+    impl fmt::Display for Target<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            fmt_target(f, self.0, self.1)
+        }
+    }
+    Target(address, port).to_string()
+}
+
+/// 解析 `host:port`，IPv6 需要加方括号
+pub fn parse_target(target: &str) -> Option<(Address, u16)> {
+    if let Ok(addr) = target.parse::<SocketAddr>() {
+        return Some((Address::from(addr.ip()), addr.port()));
+    }
+    let (host, port) = target.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    if host.is_empty() || host.len() > 255 || host.contains(':') {
+        return None;
+    }
+    Some((Address::Domain(host.to_string()), port))
+}
+
+/// 构造携带 BND.ADDR / BND.PORT 的应答，地址族与 `bind_addr` 一致
+pub fn reply(rep: u8, bind_addr: SocketAddr) -> Vec<u8> {
+    reply_address(rep, &Address::from(bind_addr.ip()), bind_addr.port())
+}
+
+/// 构造 BND.ADDR 为任意地址 (包括域名) 的应答
+pub fn reply_address(rep: u8, address: &Address, port: u16) -> Vec<u8> {
+    let mut buf = vec![SOCKS_VERSION, rep, 0x00];
+    address.write(&mut buf);
+    buf.extend_from_slice(&port.to_be_bytes());
+    buf
 }
 
 /// SOCKS5 UDP 数据报文头
@@ -87,7 +175,7 @@ impl SocksRequest {
 /// | 2  |  1   |  1   | Variable |    2     | Variable |
 /// +----+------+------+----------+----------+----------+
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct UDPAssociateHeader {
     pub frag: u8,
     pub address: Address,
@@ -95,13 +183,14 @@ pub struct UDPAssociateHeader {
 }
 
 impl UDPAssociateHeader {
-    pub fn parse(buf: &[u8]) -> Result<(Self, usize), Box<dyn Error>> {
+    pub fn parse(buf: &[u8]) -> Result<(Self, usize), SocksError> {
+        let malformed = |msg: &str| SocksError::Protocol(msg.to_string());
         if buf.len() < 4 {
-            return Err("UDP packet too short".into());
+            return Err(malformed("UDP packet too short"));
         }
 
         if buf[0] != 0x00 || buf[1] != 0x00 {
-            return Err("Invalid reserved filds in UDP Header".into());
+            return Err(malformed("Invalid reserved filds in UDP Header"));
         }
 
         let frag = buf[2];
@@ -110,7 +199,7 @@ impl UDPAssociateHeader {
         let (address, port, consumed) = match atyp {
             ATYP_IPV4 => {
                 if buf.len() < 10 {
-                    return Err("IPv4 packet too short".into());
+                    return Err(malformed("IPv4 packet too short"));
                 }
                 let ip = Ipv4Addr::new(buf[4], buf[5], buf[6], buf[7]);
                 let port = u16::from_be_bytes([buf[8], buf[9]]);
@@ -118,25 +207,30 @@ impl UDPAssociateHeader {
             }
             ATYP_IPV6 => {
                 if buf.len() < 22 {
-                    return Err("IPv6 packet too short".into());
+                    return Err(malformed("IPv6 packet too short"));
                 }
-                let bytes: [u8; 16] = buf[4..20].try_into()?;
+                let mut bytes = [0u8; 16];
+                bytes.copy_from_slice(&buf[4..20]);
                 let ip = Ipv6Addr::from(bytes);
                 let port = u16::from_be_bytes([buf[20], buf[21]]);
                 (Address::IpV6(ip), port, 22)
             }
             ATYP_DOMAIN => {
+                if buf.len() < 5 {
+                    return Err(malformed("Domain packet too short"));
+                }
                 let len = buf[4] as usize;
                 if buf.len() < 5 + len + 2 {
-                    return Err("Domain packet too short".into());
+                    return Err(malformed("Domain packet too short"));
                 }
                 let domain_bytes = &buf[5..5 + len];
-                let domain = String::from_utf8(domain_bytes.to_vec())?;
+                let domain = String::from_utf8(domain_bytes.to_vec())
+                    .map_err(|_| malformed("wrong domain"))?;
                 let port_bytes = &buf[5 + len..5 + len + 2];
                 let port = u16::from_be_bytes([port_bytes[0], port_bytes[1]]);
                 (Address::Domain(domain), port, 5 + len + 2)
             }
-            _ => return Err(format!("Unknown ATYP: {}", atyp).into()),
+            _ => return Err(SocksError::AddressTypeNotSupported(atyp)),
         };
 
         Ok((
@@ -150,22 +244,13 @@ impl UDPAssociateHeader {
     }
     pub fn write(&self, buf: &mut Vec<u8>) {
         buf.extend_from_slice(&[0x00, 0x00, self.frag]);
-
-        match &self.address {
-            Address::IpV4(ip) => {
-                buf.push(ATYP_IPV4);
-                buf.extend_from_slice(&ip.octets());
-            }
-            Address::Domain(domain) => {
-                buf.push(ATYP_DOMAIN);
-                buf.push(domain.len() as u8);
-                buf.extend_from_slice(domain.as_bytes());
-            }
-            Address::IpV6(ip) => {
-                buf.push(ATYP_IPV6);
-                buf.extend_from_slice(&ip.octets());
-            }
-        }
+        self.address.write(buf);
         buf.extend_from_slice(&self.port.to_be_bytes());
     }
 }
+
+impl fmt::Display for UDPAssociateHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_target(f, &self.address, self.port)
+    }
+}
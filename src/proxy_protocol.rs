@@ -0,0 +1,222 @@
+use std::error::Error;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing::debug;
+
+use crate::auth::UserConfig;
+use crate::config::ProxyProtocolConfig;
+use crate::rules::Cidr;
+
+/// v1 头部的最大长度 (含 CRLF)
+const V1_MAX_LEN: usize = 107;
+/// v2 签名
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V2_CMD_LOCAL: u8 = 0x00;
+const V2_CMD_PROXY: u8 = 0x01;
+const V2_FAMILY_INET: u8 = 0x10;
+const V2_FAMILY_INET6: u8 = 0x20;
+const V2_TRANSPORT_STREAM: u8 = 0x01;
+/// 自定义 TLV (0xE0-0xEF 留给应用)：认证通过的用户名
+pub const PP2_TYPE_USER: u8 = 0xE0;
+
+/// 接受 PROXY protocol 头部的来源 (负载均衡器)
+#[derive(Debug)]
+pub struct ProxyProtocol {
+    trusted: Vec<Cidr>,
+}
+
+impl ProxyProtocol {
+    pub fn new(config: &ProxyProtocolConfig) -> Result<Self, Box<dyn Error>> {
+        let trusted = config
+            .trusted
+            .iter()
+            .map(|s| Cidr::parse(s).ok_or_else(|| format!("invalid trusted source: {}", s)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ProxyProtocol { trusted })
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+/// 来自可信来源的连接必须以 PROXY protocol 头部开头，返回其中的客户端地址；
+/// 其他来源 (或未开启) 时原样返回 `peer_addr`
+pub async fn accept(
+    socket: &mut TcpStream,
+    peer_addr: SocketAddr,
+    config: &UserConfig,
+) -> io::Result<SocketAddr> {
+    let Some(proxy_protocol) = &config.proxy_protocol else {
+        return Ok(peer_addr);
+    };
+    if !proxy_protocol.is_trusted(peer_addr.ip()) {
+        return Ok(peer_addr);
+    }
+
+    // 头部是客户端发来的第一段数据，与方法协商共用时限 (0 表示不限制)
+    let secs = config.timeouts.greeting;
+    let source = if secs == 0 {
+        read_header(socket).await?
+    } else {
+        timeout(Duration::from_secs(secs), read_header(socket))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "PROXY header timed out"))??
+    };
+    match source {
+        Some(source) => {
+            debug!("PROXY header from {}: client {}", peer_addr, source);
+            Ok(source)
+        }
+        None => Ok(peer_addr),
+    }
+}
+
+/// 读取 v1 或 v2 头部，只消费头部本身；LOCAL / UNKNOWN 返回 None
+pub async fn read_header<R: AsyncRead + Unpin>(socket: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut prefix = [0u8; 6];
+    socket.read_exact(&mut prefix).await?;
+    if &prefix == b"PROXY " {
+        return read_v1(socket).await;
+    }
+    if prefix != V2_SIGNATURE[..6] {
+        return Err(invalid("missing PROXY header"));
+    }
+
+    let mut header = [0u8; 16];
+    header[..6].copy_from_slice(&prefix);
+    socket.read_exact(&mut header[6..]).await?;
+    if header[..12] != V2_SIGNATURE || header[12] >> 4 != 2 {
+        return Err(invalid("invalid PROXY v2 signature"));
+    }
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let mut body = vec![0u8; len];
+    socket.read_exact(&mut body).await?;
+
+    match header[12] & 0x0F {
+        V2_CMD_LOCAL => return Ok(None),
+        V2_CMD_PROXY => {}
+        cmd => return Err(invalid(&format!("unknown PROXY v2 command {}", cmd))),
+    }
+    // 只关心地址族，TCP / UDP 都接受；其他地址族 (UNIX 等) 视为未知
+    let source = match header[13] & 0xF0 {
+        V2_FAMILY_INET if len >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            SocketAddr::new(ip.into(), u16::from_be_bytes([body[8], body[9]]))
+        }
+        V2_FAMILY_INET6 if len >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&body[..16]);
+            let ip = Ipv6Addr::from(octets);
+            SocketAddr::new(ip.into(), u16::from_be_bytes([body[32], body[33]]))
+        }
+        V2_FAMILY_INET | V2_FAMILY_INET6 => return Err(invalid("truncated PROXY v2 address")),
+        _ => return Ok(None),
+    };
+    Ok(Some(source))
+}
+
+/// `PROXY TCP4 <src> <dst> <sport> <dport>\r\n`，逐字节读取以免多读后面的 SOCKS5 数据
+async fn read_v1<R: AsyncRead + Unpin>(socket: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut line = Vec::with_capacity(V1_MAX_LEN);
+    line.extend_from_slice(b"PROXY ");
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+        line.push(socket.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("invalid PROXY v1 header"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", src, _dst, sport, _dport] => {
+            let ip: IpAddr = src
+                .parse()
+                .map_err(|_| invalid("invalid PROXY v1 source"))?;
+            let port: u16 = sport
+                .parse()
+                .map_err(|_| invalid("invalid PROXY v1 port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("invalid PROXY v1 header")),
+    }
+}
+
+/// 构造发往后端的头部：`source` 是客户端地址，`dest` 是出站连接的目标地址；
+/// v1 没有 TLV，只有 v2 携带 `user`
+pub fn header(version: u8, source: SocketAddr, dest: SocketAddr, user: Option<&str>) -> Vec<u8> {
+    // 两端地址族不同时统一成 IPv6 (IPv4-mapped)
+    let (source, dest) = match (source, dest) {
+        (SocketAddr::V4(_), SocketAddr::V4(_)) | (SocketAddr::V6(_), SocketAddr::V6(_)) => {
+            (source, dest)
+        }
+        _ => (to_ipv6(source), to_ipv6(dest)),
+    };
+
+    if version == 1 {
+        let proto = if source.is_ipv4() { "TCP4" } else { "TCP6" };
+        return format!(
+            "PROXY {} {} {} {} {}\r\n",
+            proto,
+            source.ip(),
+            dest.ip(),
+            source.port(),
+            dest.port()
+        )
+        .into_bytes();
+    }
+
+    let mut body = Vec::with_capacity(36 + 3 + 255);
+    let family = match (source.ip(), dest.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            body.extend_from_slice(&src.octets());
+            body.extend_from_slice(&dst.octets());
+            V2_FAMILY_INET
+        }
+        (src, dst) => {
+            body.extend_from_slice(&ipv6_octets(src));
+            body.extend_from_slice(&ipv6_octets(dst));
+            V2_FAMILY_INET6
+        }
+    };
+    body.extend_from_slice(&source.port().to_be_bytes());
+    body.extend_from_slice(&dest.port().to_be_bytes());
+    if let Some(user) = user {
+        body.push(PP2_TYPE_USER);
+        body.extend_from_slice(&(user.len() as u16).to_be_bytes());
+        body.extend_from_slice(user.as_bytes());
+    }
+
+    let mut buf = Vec::with_capacity(16 + body.len());
+    buf.extend_from_slice(&V2_SIGNATURE);
+    buf.push(0x20 | V2_CMD_PROXY);
+    buf.push(family | V2_TRANSPORT_STREAM);
+    buf.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&body);
+    buf
+}
+
+fn to_ipv6(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(ip.to_ipv6_mapped().into(), addr.port()),
+        IpAddr::V6(_) => addr,
+    }
+}
+
+fn ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, error, info, warn};
+
+use crate::auth::UserConfig;
+use crate::config::AgentConfig;
+use crate::consts::*;
+use crate::crypto;
+use crate::error::SocksError;
+use crate::idle;
+use crate::mux::{MuxStream, Session};
+use crate::protocol::{Address, SocksRequest};
+use crate::transport::Stream;
+use crate::tunnel::MUX_KEEPALIVE;
+
+/// 保留的伪域名后缀：CONNECT `<name>.tunnel` 会经名为 name 的代理端出站
+const TUNNEL_SUFFIX: &str = ".tunnel";
+/// 代理端断线后重连的间隔
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// 服务端：已注册的代理端，名字到其多路复用会话
+#[derive(Default)]
+pub struct Registry {
+    agents: Mutex<HashMap<String, (u64, Session)>>,
+    next_id: AtomicU64,
+}
+
+impl Registry {
+    /// 名字已被仍在线的代理端占用时返回 None，否则返回本次注册的 ID
+    fn register(&self, name: &str, session: &Session) -> Option<u64> {
+        let name = name.to_ascii_lowercase();
+        let mut agents = self.agents.lock().unwrap();
+        if agents.get(&name).is_some_and(|(_, s)| !s.is_closed()) {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        agents.insert(name, (id, session.clone()));
+        Some(id)
+    }
+
+    /// 只移除自己的注册，名字可能已经被重连后的新会话占用
+    fn unregister(&self, name: &str, id: u64) {
+        let name = name.to_ascii_lowercase();
+        let mut agents = self.agents.lock().unwrap();
+        if agents.get(&name).is_some_and(|(current, _)| *current == id) {
+            agents.remove(&name);
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<Session> {
+        let agents = self.agents.lock().unwrap();
+        agents
+            .get(&name.to_ascii_lowercase())
+            .map(|(_, session)| session.clone())
+            .filter(|session| !session.is_closed())
+    }
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let agents = self.agents.lock().unwrap();
+        f.debug_set().entries(agents.keys()).finish()
+    }
+}
+
+/// `<name>.tunnel` 中的 name；与规则中的域名一样不区分大小写、忽略末尾的 `.`
+pub fn tunnel_name(domain: &str) -> Option<&str> {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    let split = domain.len().checked_sub(TUNNEL_SUFFIX.len())?;
+    let name = domain.get(..split)?;
+    let suffix = domain.get(split..)?;
+    (suffix.eq_ignore_ascii_case(TUNNEL_SUFFIX) && !name.is_empty()).then_some(name)
+}
+
+/// 服务端处理代理端的注册流：成功后保持打开，流关闭即注销
+pub async fn register<S: Stream>(
+    mut socket: S,
+    request: SocksRequest,
+    session: &Session,
+    config: &UserConfig,
+) -> Result<(), SocksError> {
+    let Address::Domain(name) = &request.address else {
+        let e = SocksError::AddressTypeNotSupported(ATYP_DOMAIN);
+        socket.write_all(&e.reply()).await?;
+        return Err(e);
+    };
+    let Some(id) = config.reverse.register(name, session) else {
+        warn!("反向隧道名字已被占用: {}", name);
+        let e = SocksError::RuleDenied(format!("reverse tunnel {} already registered", name));
+        socket.write_all(&e.reply()).await?;
+        return Err(e);
+    };
+    info!("Reverse tunnel {} registered", name);
+    reply(&mut socket, REP_SUCCESS).await?;
+
+    // 代理端不会在注册流上发数据，读到 EOF 或连接断开即注销
+    let mut buf = [0u8; 1];
+    let _ = socket.read(&mut buf).await;
+    config.reverse.unregister(name, id);
+    info!("Reverse tunnel {} unregistered", name);
+    Ok(())
+}
+
+/// 服务端处理 CONNECT `<name>.tunnel`：在该代理端的会话上打开一个流，
+/// 代理端完成出站后把它的应答转给客户端，之后双向转发
+pub async fn connect<S: Stream>(
+    mut socket: S,
+    name: &str,
+    request: &SocksRequest,
+    config: &UserConfig,
+) -> Result<(), SocksError> {
+    let Some(session) = config.reverse.get(name) else {
+        warn!("反向隧道不存在: {}", name);
+        let e = SocksError::HostUnreachable(format!("reverse tunnel {} not registered", name));
+        socket.write_all(&e.reply()).await?;
+        return Err(e);
+    };
+    let mut stream = match session.open() {
+        Ok(stream) => stream,
+        Err(e) => {
+            let e = SocksError::Io(e);
+            socket.write_all(&e.reply()).await?;
+            return Err(e);
+        }
+    };
+
+    let mut buf = Vec::new();
+    SocksRequest {
+        cmd: CMD_CONNECT,
+        address: Address::Domain(name.to_string()),
+        port: request.port,
+    }
+    .write(&mut buf);
+    stream.write_all(&buf).await?;
+
+    let agent_timeout = Duration::from_secs(config.timeout as u64);
+    let mut agent_reply = [0u8; 10];
+    let result = match timeout(agent_timeout, stream.read_exact(&mut agent_reply)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(SocksError::Io(e)),
+        Err(_) => Err(SocksError::TimedOut(format!("reverse tunnel {}", name))),
+    };
+    if let Err(e) = result {
+        socket.write_all(&e.reply()).await?;
+        return Err(e);
+    }
+    socket.write_all(&agent_reply).await?;
+    if agent_reply[1] != REP_SUCCESS {
+        return Err(SocksError::Upstream(
+            format!("reverse tunnel {}", name),
+            agent_reply[1],
+        ));
+    }
+
+    let idle = Duration::from_secs(config.timeouts.idle);
+    match idle::copy(&mut socket, &mut stream, idle).await? {
+        Some((up, down)) => debug!("反向隧道传输完成: 上行 {}b, 下行 {}b", up, down),
+        None => info!("反向隧道 {} 空闲超过 {}s，关闭", name, idle.as_secs()),
+    }
+    Ok(())
+}
+
+/// 代理端：连接服务端的隧道监听并注册服务，断线后自动重连；
+/// 转发两个方向都超过 `idle` 没有数据时关闭 (0 表示不限制)
+pub async fn run_agent(
+    agent: AgentConfig,
+    connect_timeout: u8,
+    idle: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let services: Arc<HashMap<String, String>> = Arc::new(
+        agent
+            .services
+            .iter()
+            .map(|s| (s.name.clone(), s.target.clone()))
+            .collect(),
+    );
+    if services.is_empty() {
+        return Err("agent has no services".into());
+    }
+    let connect_timeout = Duration::from_secs(connect_timeout as u64);
+
+    loop {
+        if let Err(e) = agent_session(&agent, &services, connect_timeout, idle).await {
+            warn!("Reverse tunnel to {} failed: {}", agent.server, e);
+        }
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn agent_session(
+    agent: &AgentConfig,
+    services: &Arc<HashMap<String, String>>,
+    connect_timeout: Duration,
+    idle: Duration,
+) -> io::Result<()> {
+    let timed_out = |_| io::Error::from(io::ErrorKind::TimedOut);
+    let socket = timeout(connect_timeout, TcpStream::connect(&agent.server))
+        .await
+        .map_err(timed_out)??;
+    socket.set_nodelay(true)?;
+    // 握手和注册应答同样受连接超时约束，服务端卡住时重连循环不会一直挂起
+    let stream = timeout(
+        connect_timeout,
+        crypto::connect(socket, agent.key.as_bytes()),
+    )
+    .await
+    .map_err(timed_out)??;
+    let (session, mut incoming) = Session::new(stream, true, MUX_KEEPALIVE);
+    info!("Reverse tunnel to {} established", agent.server);
+
+    // 注册流在会话期间一直保持打开，丢弃即注销
+    let mut registrations = Vec::with_capacity(services.len());
+    for name in services.keys() {
+        let mut stream = session.open()?;
+        let mut buf = Vec::new();
+        SocksRequest {
+            cmd: CMD_REGISTER,
+            address: Address::Domain(name.clone()),
+            port: 0,
+        }
+        .write(&mut buf);
+        stream.write_all(&buf).await?;
+        let mut reply = [0u8; 10];
+        timeout(connect_timeout, stream.read_exact(&mut reply))
+            .await
+            .map_err(timed_out)??;
+        if reply[1] == REP_SUCCESS {
+            info!("Registered {}{}", name, TUNNEL_SUFFIX);
+            registrations.push(stream);
+        } else {
+            warn!("Register {} rejected: 0x{:02x}", name, reply[1]);
+        }
+    }
+
+    while let Some(stream) = incoming.accept().await {
+        let services = services.clone();
+        tokio::spawn(async move {
+            if let Err(e) = agent_connect(stream, &services, connect_timeout, idle).await {
+                error!("[Error] reverse tunnel : {}", e);
+            }
+        });
+    }
+    drop(registrations);
+    Err(io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "session closed",
+    ))
+}
+
+/// 代理端处理服务端打开的流：连接名字对应的本地服务并回复应答
+async fn agent_connect(
+    mut stream: MuxStream,
+    services: &HashMap<String, String>,
+    connect_timeout: Duration,
+    idle: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let request = SocksRequest::read_from(&mut stream).await?;
+    let target = match &request.address {
+        Address::Domain(name) => services
+            .iter()
+            .find(|(service, _)| service.eq_ignore_ascii_case(name))
+            .map(|(_, target)| target),
+        _ => None,
+    };
+    let Some(target) = target else {
+        reply(&mut stream, REP_HOST_UNREACHABLE).await?;
+        return Err(format!("unknown service: {}", request).into());
+    };
+    info!("Reverse tunnel {} -> {}", request, target);
+
+    let mut server_socket = match timeout(connect_timeout, TcpStream::connect(target)).await {
+        Ok(Ok(socket)) => socket,
+        Ok(Err(e)) => {
+            let rep = match e.kind() {
+                io::ErrorKind::ConnectionRefused => REP_CONNECTION_REFUSED,
+                _ => REP_HOST_UNREACHABLE,
+            };
+            reply(&mut stream, rep).await?;
+            return Err(format!("连接服务 {} 失败: {}", target, e).into());
+        }
+        Err(_) => {
+            reply(&mut stream, REP_TTL_EXPIRED).await?;
+            return Err(format!("连接服务 {} 超时", target).into());
+        }
+    };
+    reply(&mut stream, REP_SUCCESS).await?;
+
+    match idle::copy(&mut stream, &mut server_socket, idle).await? {
+        Some((up, down)) => debug!("反向隧道传输完成: 上行 {}b, 下行 {}b", up, down),
+        None => info!("反向隧道 {} 空闲超过 {}s，关闭", target, idle.as_secs()),
+    }
+    Ok(())
+}
+
+async fn reply<S: Stream>(socket: &mut S, rep: u8) -> io::Result<()> {
+    let reply = [SOCKS_VERSION, rep, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0];
+    socket.write_all(&reply).await
+}
@@ -0,0 +1,135 @@
+use std::error::Error;
+use std::net::IpAddr;
+
+use crate::config::RulesConfig;
+use crate::protocol::Address;
+
+/// 域名后缀集合：`example.com` 匹配自身及所有子域名
+#[derive(Debug, Default)]
+pub struct DomainSet {
+    suffixes: Vec<String>,
+}
+
+impl DomainSet {
+    pub fn new(domains: &[String]) -> Self {
+        DomainSet {
+            suffixes: domains.iter().map(|d| normalize(d)).collect(),
+        }
+    }
+
+    pub fn matches(&self, domain: &str) -> bool {
+        self.longest_match(domain).is_some()
+    }
+
+    /// 匹配到的最长后缀的长度，用于在多条规则之间选最具体的一条
+    pub fn longest_match(&self, domain: &str) -> Option<usize> {
+        let domain = normalize(domain);
+        self.suffixes
+            .iter()
+            .filter(|suffix| {
+                domain == **suffix
+                    || (domain.ends_with(suffix.as_str())
+                        && domain.as_bytes()[domain.len() - suffix.len() - 1] == b'.')
+            })
+            .map(|suffix| suffix.len())
+            .max()
+    }
+}
+
+/// `ip` 或 `ip/prefix`
+#[derive(Debug)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (ip, prefix) = match s.split_once('/') {
+            Some((ip, prefix)) => (ip.parse::<IpAddr>().ok()?, Some(prefix.parse().ok()?)),
+            None => (s.parse::<IpAddr>().ok()?, None),
+        };
+        let ip = ip.to_canonical();
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Cidr {
+            network: ip,
+            prefix,
+        })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 小写并去掉末尾的 `.`
+fn normalize(domain: &str) -> String {
+    domain.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// 需要发送 PROXY protocol 头部的目标
+#[derive(Debug)]
+struct ProxyHeaderRule {
+    domains: DomainSet,
+    networks: Vec<Cidr>,
+    version: u8,
+}
+
+/// 访问规则，CONNECT、UDP 和 DNS 共用
+#[derive(Debug, Default)]
+pub struct Rules {
+    block: DomainSet,
+    proxy_protocol: Vec<ProxyHeaderRule>,
+}
+
+impl Rules {
+    pub fn new(config: &RulesConfig) -> Result<Self, Box<dyn Error>> {
+        let mut proxy_protocol = Vec::with_capacity(config.proxy_protocol.len());
+        for rule in &config.proxy_protocol {
+            if rule.version != 1 && rule.version != 2 {
+                return Err(format!("invalid PROXY protocol version: {}", rule.version).into());
+            }
+            let networks = rule
+                .networks
+                .iter()
+                .map(|s| Cidr::parse(s).ok_or_else(|| format!("invalid network: {}", s)))
+                .collect::<Result<Vec<_>, _>>()?;
+            proxy_protocol.push(ProxyHeaderRule {
+                domains: DomainSet::new(&rule.domains),
+                networks,
+                version: rule.version,
+            });
+        }
+        Ok(Rules {
+            block: DomainSet::new(&config.block),
+            proxy_protocol,
+        })
+    }
+
+    /// 域名是否在黑名单中
+    pub fn is_blocked(&self, domain: &str) -> bool {
+        self.block.matches(domain)
+    }
+
+    /// CONNECT 到 `address` (实际连上 `ip`) 时要发送的 PROXY protocol 版本，第一条匹配的规则生效
+    pub fn proxy_header(&self, address: &Address, ip: IpAddr) -> Option<u8> {
+        self.proxy_protocol
+            .iter()
+            .find(|rule| {
+                matches!(address, Address::Domain(domain) if rule.domains.matches(domain))
+                    || rule.networks.iter().any(|cidr| cidr.contains(ip))
+            })
+            .map(|rule| rule.version)
+    }
+}
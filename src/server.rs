@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+
+use crate::auth::{User, UserConfig};
+use crate::config::{
+    DnsConfig, ProxyProtocolConfig, RulesConfig, TimeoutConfig, UdpConfig, UdpOverride,
+};
+use crate::dns::Dns;
+use crate::error::SocksError;
+use crate::handler;
+use crate::limits::Associations;
+use crate::ports::UdpPorts;
+use crate::proxy_protocol::{self, ProxyProtocol};
+use crate::reverse::Registry;
+use crate::rules::Rules;
+use crate::transport::Stream;
+
+/// 可嵌入的 SOCKS5 服务端
+///
+/// ```no_run
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let server = proxy::Server::builder()
+///     .user("admin", "secret123")
+///     .timeout(10)
+///     .build()
+///     .await?;
+/// let listener = tokio::net::TcpListener::bind("127.0.0.1:1080").await?;
+/// server.serve(listener).await
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Server {
+    config: Arc<UserConfig>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// 所有监听共享的配置和状态 (关联计数、端口、解析器缓存等)
+    pub fn config(&self) -> &Arc<UserConfig> {
+        &self.config
+    }
+
+    /// 在 `listener` 上接受连接，每个连接一个任务，直到 accept 出错
+    pub async fn serve(&self, listener: TcpListener) -> Result<(), Box<dyn Error>> {
+        info!("SOCKS5 Server running on {}", listener.local_addr()?);
+
+        loop {
+            let (mut socket, addr) = listener.accept().await?;
+            let config = self.config.clone();
+
+            tokio::spawn(async move {
+                let addr = match proxy_protocol::accept(&mut socket, addr, &config).await {
+                    Ok(addr) => addr,
+                    Err(e) => {
+                        warn!("PROXY header from {:?} rejected: {}", addr, e);
+                        return;
+                    }
+                };
+                if let Err(e) = handler::process(socket, addr, config.as_ref()).await {
+                    error!("[Error] from {:?} [{}] : {}", addr, e.label(), e);
+                }
+            });
+        }
+    }
+
+    /// 处理单个已建立的连接 (协商、认证、请求)，可用于任意字节流，如内存中的 duplex
+    pub async fn process<S: Stream>(
+        &self,
+        socket: S,
+        peer_addr: SocketAddr,
+    ) -> Result<(), SocksError> {
+        handler::process(socket, peer_addr, &self.config).await
+    }
+}
+
+/// [`Server`] 的构建器，未设置的项与配置文件省略时的默认值相同
+#[derive(Debug, Default)]
+pub struct ServerBuilder {
+    users: Vec<User>,
+    timeout: Option<u8>,
+    splice: Option<bool>,
+    timeouts: TimeoutConfig,
+    udp: UdpConfig,
+    rules: RulesConfig,
+    dns: DnsConfig,
+    proxy_protocol: Option<ProxyProtocolConfig>,
+}
+
+impl ServerBuilder {
+    /// 添加一个用户名/密码用户；没有任何用户时不要求认证
+    pub fn user(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.users.push(User {
+            username: username.into(),
+            password: password.into(),
+            udp: UdpOverride::default(),
+        });
+        self
+    }
+
+    pub fn users(mut self, users: Vec<User>) -> Self {
+        self.users.extend(users);
+        self
+    }
+
+    /// 出站连接和解析的超时 (秒)，默认 5
+    pub fn timeout(mut self, timeout: u8) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// TCP 转发是否使用 splice 零拷贝 (仅 Linux)，默认开启；关闭后使用 `copy_bidirectional`
+    pub fn splice(mut self, splice: bool) -> Self {
+        self.splice = Some(splice);
+        self
+    }
+
+    /// 客户端协商各阶段 (greeting、认证、请求) 和转发空闲的超时
+    pub fn timeouts(mut self, timeouts: TimeoutConfig) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn udp(mut self, udp: UdpConfig) -> Self {
+        self.udp = udp;
+        self
+    }
+
+    pub fn rules(mut self, rules: RulesConfig) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn dns(mut self, dns: DnsConfig) -> Self {
+        self.dns = dns;
+        self
+    }
+
+    pub fn proxy_protocol(mut self, proxy_protocol: ProxyProtocolConfig) -> Self {
+        self.proxy_protocol = Some(proxy_protocol);
+        self
+    }
+
+    /// 校验配置并分配共享状态；配置了固定的 UDP 中继端口时会在这里绑定
+    pub async fn build(self) -> Result<Server, Box<dyn Error>> {
+        let rules = Arc::new(Rules::new(&self.rules)?);
+        let dns = Arc::new(Dns::new(&self.dns, rules.clone())?);
+        let proxy_protocol = match &self.proxy_protocol {
+            Some(proxy_protocol) => Some(Arc::new(ProxyProtocol::new(proxy_protocol)?)),
+            None => None,
+        };
+        let config = UserConfig {
+            users: self.users,
+            timeout: self.timeout.unwrap_or(5),
+            splice: self.splice.unwrap_or(true),
+            timeouts: self.timeouts,
+            associations: Arc::new(Associations::default()),
+            udp_ports: Arc::new(UdpPorts::new(&self.udp).await?),
+            udp: self.udp,
+            rules,
+            dns,
+            reverse: Arc::new(Registry::default()),
+            proxy_protocol,
+        };
+        Ok(Server {
+            config: Arc::new(config),
+        })
+    }
+}
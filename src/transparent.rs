@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::Interest;
+use tokio::net::{TcpListener, TcpSocket, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tracing::{debug, error, info, warn};
+
+use crate::auth::UserConfig;
+use crate::batch;
+use crate::config::{TransparentMode, UdpConfig};
+use crate::consts::*;
+use crate::handler;
+use crate::limits::{AssociationGuard, TokenBucket};
+use crate::metrics::{self, UdpDrop};
+
+/// 每个 UDP 会话排队等待发往目标的报文上限
+const UDP_SESSION_QUEUE: usize = 64;
+
+/// 足够放下一个 sockaddr_in6 的控制消息，按 cmsghdr 对齐
+type Control = [u64; 8];
+
+/// 透明代理的 TCP 监听，TPROXY 模式需要在 bind 之前设置 IP_TRANSPARENT
+pub async fn bind(listen: &str, mode: TransparentMode) -> io::Result<TcpListener> {
+    let addr = resolve_listen(listen).await?;
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    if mode == TransparentMode::Tproxy {
+        set_transparent(socket.as_raw_fd(), addr)?;
+    }
+    socket.bind(addr)?;
+    socket.listen(1024)
+}
+
+/// TPROXY 的 UDP 监听：接收时带上报文的原始目标地址
+pub async fn bind_udp(listen: &str) -> io::Result<UdpSocket> {
+    let addr = resolve_listen(listen).await?;
+    let socket = UdpSocket::bind(addr).await?;
+    let fd = socket.as_raw_fd();
+    set_transparent(fd, addr)?;
+    setsockopt(fd, libc::SOL_IP, libc::IP_RECVORIGDSTADDR, 1)?;
+    if addr.is_ipv6() {
+        setsockopt(fd, libc::SOL_IPV6, libc::IPV6_RECVORIGDSTADDR, 1)?;
+    }
+    Ok(socket)
+}
+
+async fn resolve_listen(listen: &str) -> io::Result<SocketAddr> {
+    tokio::net::lookup_host(listen)
+        .await?
+        .next()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid listen address: {}", listen),
+            )
+        })
+}
+
+/// 接受被 netfilter 重定向的连接，按原始目标出站
+pub async fn serve(
+    listener: TcpListener,
+    mode: TransparentMode,
+    config: Arc<UserConfig>,
+) -> Result<(), Box<dyn Error>> {
+    let listen_addr = listener.local_addr()?;
+    info!("Transparent proxy ({:?}) running on {}", mode, listen_addr);
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let config = config.clone();
+
+        tokio::spawn(async move {
+            let target = match original_destination(&socket, mode) {
+                Ok(target) => target,
+                Err(e) => {
+                    error!("[Error] from {:?} : 无法取得原始目标: {}", addr, e);
+                    return;
+                }
+            };
+            if is_listener(target, listen_addr) {
+                warn!("拒绝直接发往透明代理端口的连接: {}", addr);
+                return;
+            }
+            if let Err(e) = handler::process_transparent(socket, target, config.as_ref()).await {
+                error!("[Error] from {:?} [{}] : {}", addr, e.label(), e);
+            }
+        });
+    }
+}
+
+/// REDIRECT 用 SO_ORIGINAL_DST 取 conntrack 记录的目标，TPROXY 下本地地址就是原始目标
+fn original_destination(socket: &TcpStream, mode: TransparentMode) -> io::Result<SocketAddr> {
+    let local = socket.local_addr()?;
+    let local = SocketAddr::new(local.ip().to_canonical(), local.port());
+    if mode == TransparentMode::Tproxy {
+        return Ok(local);
+    }
+
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of_val(&storage) as libc::socklen_t;
+    let (level, name) = match local {
+        SocketAddr::V4(_) => (libc::SOL_IP, libc::SO_ORIGINAL_DST),
+        SocketAddr::V6(_) => (libc::SOL_IPV6, libc::IP6T_SO_ORIGINAL_DST),
+    };
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &mut storage as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    batch::to_socket_addr(&storage)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown address family"))
+}
+
+/// 原始目标就是监听地址本身时说明连接没有经过重定向，继续转发会形成回环
+fn is_listener(target: SocketAddr, listen_addr: SocketAddr) -> bool {
+    let listen_ip = listen_addr.ip().to_canonical();
+    target.port() == listen_addr.port() && (listen_ip.is_unspecified() || target.ip() == listen_ip)
+}
+
+/// TPROXY UDP：按 (客户端, 原始目标) 建立会话，出站用普通 socket，
+/// 回包从绑定在原始目标地址上的透明 socket 发回客户端；
+/// 会话数、速率、报文大小沿用 UDP ASSOCIATE 的限制
+pub async fn serve_udp(socket: UdpSocket, config: Arc<UserConfig>) -> Result<(), Box<dyn Error>> {
+    let listen_addr = socket.local_addr()?;
+    info!("Transparent UDP proxy running on {}", listen_addr);
+
+    let mut sessions: HashMap<(SocketAddr, SocketAddr), mpsc::Sender<Vec<u8>>> = HashMap::new();
+    let mut buf = vec![0u8; MAX_UDP_SIZE as usize];
+
+    loop {
+        let received = socket
+            .async_io(Interest::READABLE, || {
+                recv_original(socket.as_raw_fd(), &mut buf)
+            })
+            .await;
+        let (len, client, target) = match received {
+            Ok(received) => received,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                debug!("drop udp without original destination: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if is_listener(target, listen_addr) {
+            debug!(
+                "drop udp addressed to the transparent listener from {}",
+                client
+            );
+            continue;
+        }
+        if len > config.udp.max_datagram_size {
+            metrics::udp_drop(UdpDrop::Oversized);
+            continue;
+        }
+
+        let key = (client, target);
+        if let Some(tx) = sessions.get(&key)
+            && tx.try_send(buf[..len].to_vec()).is_ok()
+        {
+            continue;
+        }
+
+        // 会话不存在、已经关闭或队列已满：关闭的会话重新建立，队列满时丢弃
+        if sessions.get(&key).is_some_and(|tx| !tx.is_closed()) {
+            debug!("udp session {} -> {} queue full", client, target);
+            continue;
+        }
+        sessions.retain(|_, tx| !tx.is_closed());
+        let owner = client.ip().to_canonical().to_string();
+        let Some(guard) = config.associations.acquire(&owner, &config.udp) else {
+            debug!("udp session for {} rejected: limit reached", client);
+            metrics::udp_drop(UdpDrop::Associations);
+            continue;
+        };
+        let (tx, rx) = mpsc::channel(UDP_SESSION_QUEUE);
+        match open_udp_session(client, target, rx, guard, &config.udp).await {
+            Ok(()) => {
+                let _ = tx.try_send(buf[..len].to_vec());
+                sessions.insert(key, tx);
+            }
+            Err(e) => warn!("udp session {} -> {} failed: {}", client, target, e),
+        }
+    }
+}
+
+async fn open_udp_session(
+    client: SocketAddr,
+    target: SocketAddr,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    guard: AssociationGuard,
+    udp: &UdpConfig,
+) -> io::Result<()> {
+    let bind_addr: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let outbound = UdpSocket::bind(bind_addr).await?;
+    outbound.connect(target).await?;
+    let reply = bind_transparent_udp(target)?;
+    debug!("transparent udp session {} -> {}", client, target);
+
+    let session_timeout = Duration::from_secs(udp.session_timeout);
+    let mut rate_out = TokenBucket::new(udp.rate_limit);
+    let mut rate_in = TokenBucket::new(udp.rate_limit);
+    tokio::spawn(async move {
+        let _guard = guard;
+        let mut buf = vec![0u8; MAX_UDP_SIZE as usize];
+        loop {
+            tokio::select! {
+                data = rx.recv() => {
+                    let Some(data) = data else { break };
+                    if !rate_out.take() {
+                        metrics::udp_drop(UdpDrop::RateLimited);
+                        continue;
+                    }
+                    if let Err(e) = outbound.send(&data).await {
+                        debug!("udp send to {} failed: {}", target, e);
+                    }
+                }
+                result = outbound.recv(&mut buf) => match result {
+                    Ok(len) => {
+                        if !rate_in.take() {
+                            metrics::udp_drop(UdpDrop::RateLimited);
+                            continue;
+                        }
+                        if let Err(e) = reply.send_to(&buf[..len], client).await {
+                            debug!("udp reply to {} failed: {}", client, e);
+                        }
+                    }
+                    Err(e) => debug!("udp recv from {} failed: {}", target, e),
+                },
+                _ = sleep(session_timeout) => {
+                    debug!("transparent udp session {} -> {} expired", client, target);
+                    break;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// 接收一个报文，返回长度、来源和 IP_ORIGDSTADDR 给出的原始目标
+fn recv_original(fd: RawFd, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, SocketAddr)> {
+    let mut src: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut control: Control = [0; 8];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    hdr.msg_name = &mut src as *mut _ as *mut libc::c_void;
+    hdr.msg_namelen = mem::size_of_val(&src) as libc::socklen_t;
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+    hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    hdr.msg_controllen = mem::size_of_val(&control) as _;
+
+    let len = unsafe { libc::recvmsg(fd, &mut hdr, 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut target = None;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&hdr) };
+    while !cmsg.is_null() {
+        let c = unsafe { &*cmsg };
+        if (c.cmsg_level == libc::SOL_IP && c.cmsg_type == libc::IP_ORIGDSTADDR)
+            || (c.cmsg_level == libc::SOL_IPV6 && c.cmsg_type == libc::IPV6_ORIGDSTADDR)
+        {
+            let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+            let data_len = c.cmsg_len as usize - unsafe { libc::CMSG_LEN(0) } as usize;
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    libc::CMSG_DATA(cmsg),
+                    &mut storage as *mut _ as *mut u8,
+                    data_len.min(mem::size_of_val(&storage)),
+                );
+            }
+            target = batch::to_socket_addr(&storage);
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&hdr, cmsg) };
+    }
+
+    let canonical = |addr: SocketAddr| SocketAddr::new(addr.ip().to_canonical(), addr.port());
+    let src = batch::to_socket_addr(&src)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown source address"))?;
+    let target = target
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing IP_ORIGDSTADDR"))?;
+    Ok((len as usize, canonical(src), canonical(target)))
+}
+
+/// 绑定到非本机地址 (原始目标) 的 UDP socket，用来以目标的身份回包
+fn bind_transparent_udp(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let family = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe {
+        libc::socket(
+            family,
+            libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // 交给 std 管理，之后出错时自动关闭
+    let socket = unsafe { std::net::UdpSocket::from_raw_fd(fd) };
+    // 同一目标可能同时有多个客户端的会话
+    setsockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, 1)?;
+    set_transparent(fd, addr)?;
+
+    let (storage, len) = batch::from_socket_addr(addr);
+    let ret = unsafe { libc::bind(fd, &storage as *const _ as *const libc::sockaddr, len) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    UdpSocket::from_std(socket)
+}
+
+/// 允许绑定非本机地址、接收 TPROXY 转来的流量 (需要 CAP_NET_ADMIN)
+fn set_transparent(fd: RawFd, addr: SocketAddr) -> io::Result<()> {
+    match addr {
+        SocketAddr::V4(_) => setsockopt(fd, libc::SOL_IP, libc::IP_TRANSPARENT, 1),
+        SocketAddr::V6(_) => setsockopt(fd, libc::SOL_IPV6, libc::IPV6_TRANSPARENT, 1),
+    }
+}
+
+fn setsockopt(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
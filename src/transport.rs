@@ -0,0 +1,21 @@
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::net::TcpStream;
+
+/// 客户端连接的抽象
+///
+/// SOCKS5 流程既可以跑在裸 TCP 上，也可以跑在 WebSocket 等封装之上。
+/// 只有两端都是 TcpStream 时 `transfer` 才能走 splice 零拷贝。
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {
+    fn as_tcp(&mut self) -> Option<&mut TcpStream> {
+        None
+    }
+}
+
+impl Stream for TcpStream {
+    fn as_tcp(&mut self) -> Option<&mut TcpStream> {
+        Some(self)
+    }
+}
+
+/// 内存中的连接，用于嵌入和测试
+impl Stream for DuplexStream {}
@@ -0,0 +1,264 @@
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tracing::{debug, error, info, warn};
+
+use crate::auth::UserConfig;
+use crate::consts::*;
+use crate::crypto;
+use crate::handler;
+use crate::idle;
+use crate::mux::{MuxStream, Session};
+use crate::protocol;
+use crate::proxy_protocol;
+use crate::udp::{self, read_frame, write_frame};
+
+/// 隧道多路复用会话的保活间隔
+pub const MUX_KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// 远端节点：接受本地节点的加密连接，在其上建立多路复用会话，
+/// 每个逻辑流读取请求并完成出站
+pub async fn serve(
+    listener: TcpListener,
+    key: String,
+    config: Arc<UserConfig>,
+) -> Result<(), Box<dyn Error>> {
+    info!("Tunnel listener running on {}", listener.local_addr()?);
+    let key: Arc<[u8]> = key.into_bytes().into();
+
+    loop {
+        let (mut socket, addr) = listener.accept().await?;
+        let config = config.clone();
+        let key = key.clone();
+
+        tokio::spawn(async move {
+            let addr = match proxy_protocol::accept(&mut socket, addr, &config).await {
+                Ok(addr) => addr,
+                Err(e) => {
+                    warn!("PROXY header from {:?} rejected: {}", addr, e);
+                    return;
+                }
+            };
+            let _ = socket.set_nodelay(true);
+            // 盐值交换属于握手阶段，与 SOCKS 协商共用同一个期限
+            let handshake = async { Ok(crypto::accept(socket, &key).await?) };
+            let stream =
+                match handler::within(config.timeouts.greeting, "handshake", handshake).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Tunnel handshake from {:?} failed: {}", addr, e);
+                        return;
+                    }
+                };
+
+            let (session, mut incoming) = Session::new(stream, false, MUX_KEEPALIVE);
+            debug!("Tunnel session from {:?} established", addr);
+            while let Some(stream) = incoming.accept().await {
+                let config_clone = config.clone();
+                let session = session.clone();
+                tokio::spawn(async move {
+                    if let Err(e) =
+                        handler::process_tunnel(stream, addr, &session, config_clone.as_ref()).await
+                    {
+                        error!("[Error] from {:?} (tunnel) [{}] : {}", addr, e.label(), e);
+                    }
+                });
+            }
+            debug!("Tunnel session from {:?} closed", addr);
+        });
+    }
+}
+
+/// 本地节点：对应用暴露 SOCKS5 端口，完成协商后把每个 CONNECT / UDP ASSOCIATE
+/// 作为一个逻辑流经加密隧道交给远端节点
+pub async fn run_local(
+    listener: TcpListener,
+    server: String,
+    key: String,
+    config: Arc<UserConfig>,
+) -> Result<(), Box<dyn Error>> {
+    info!(
+        "Local SOCKS5 running on {}, tunneling to {}",
+        listener.local_addr()?,
+        server
+    );
+    let remote = Arc::new(Remote {
+        server,
+        key: key.into_bytes(),
+        timeout: config.timeout,
+        session: Mutex::new(None),
+    });
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let config_clone = config.clone();
+        let remote = remote.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_local(socket, addr, &remote, config_clone.as_ref()).await {
+                error!("[Error] from {:?} : {}", addr, e);
+            }
+        });
+    }
+}
+
+/// 到远端节点的共享连接，断开后在下一次打开流时重连
+struct Remote {
+    server: String,
+    key: Vec<u8>,
+    timeout: u8,
+    session: Mutex<Option<Session>>,
+}
+
+impl Remote {
+    async fn open_stream(&self) -> io::Result<MuxStream> {
+        let mut session = self.session.lock().await;
+        if let Some(s) = session.as_ref().filter(|s| !s.is_closed()) {
+            return s.open();
+        }
+
+        let connect_timeout = Duration::from_secs(self.timeout as u64);
+        let socket = timeout(connect_timeout, TcpStream::connect(&self.server))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        socket.set_nodelay(true)?;
+        // 盐值交换同样受连接超时约束，否则不回应的远端会让持锁的后续会话全部挂住
+        let stream = timeout(connect_timeout, crypto::connect(socket, &self.key))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+
+        // 远端不会主动打开流，丢弃 Incoming 即可
+        let (s, _) = Session::new(stream, true, MUX_KEEPALIVE);
+        info!("Tunnel session to {} established", self.server);
+        let stream = s.open();
+        *session = Some(s);
+        stream
+    }
+}
+
+async fn handle_local(
+    mut socket: TcpStream,
+    peer_addr: SocketAddr,
+    remote: &Remote,
+    config: &UserConfig,
+) -> Result<(), Box<dyn Error>> {
+    let (request, _) = handler::handshake(&mut socket, config).await?;
+    info!("Tunnel {} for {}", request, peer_addr);
+
+    let mut tunnel = match remote.open_stream().await {
+        Ok(tunnel) => tunnel,
+        Err(e) => {
+            let reply = [
+                SOCKS_VERSION,
+                REP_GENERAL_FAILURE,
+                0x00,
+                ATYP_IPV4,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ];
+            let _ = socket.write_all(&reply).await;
+            return Err(format!("连接远端节点失败: {}", e).into());
+        }
+    };
+
+    let mut buf = Vec::new();
+    request.write(&mut buf);
+    tunnel.write_all(&buf).await?;
+
+    if request.cmd == CMD_UDP_ASSOCIATE {
+        let client = udp::expected_client(&request, peer_addr);
+        return relay_udp(socket, client, tunnel).await;
+    }
+
+    // CONNECT 及其他命令：远端的应答和后续数据都原样转发，
+    // 任一方向 EOF 时只半关闭对应的写方向
+    let idle = Duration::from_secs(config.timeouts.idle);
+    match idle::copy(&mut socket, &mut tunnel, idle).await? {
+        Some((up, down)) => debug!("隧道传输完成: 上行 {}b, 下行 {}b", up, down),
+        None => info!("隧道转发空闲超过 {}s，关闭", idle.as_secs()),
+    }
+    Ok(())
+}
+
+/// 本地节点的 UDP ASSOCIATE：在本地开 UDP 端口接收应用的 SOCKS5 UDP 报文，
+/// 整包按帧经隧道发给远端，远端的回包按帧取出后原样发回应用
+async fn relay_udp(
+    mut socket: TcpStream,
+    client: SocketAddr,
+    mut tunnel: MuxStream,
+) -> Result<(), Box<dyn Error>> {
+    // 先等远端的 UDP ASSOCIATE 应答
+    let mut reply = [0u8; 10];
+    tunnel.read_exact(&mut reply).await?;
+    if reply[1] != REP_SUCCESS {
+        socket.write_all(&reply).await?;
+        return Err(format!("remote udp associate failed: 0x{:02x}", reply[1]).into());
+    }
+
+    let udp = udp::bind_relay(client.ip()).await?;
+    let listen_addr = udp.local_addr()?;
+    info!("UDP Relay (tunnel) started at port: {}", listen_addr.port());
+
+    socket
+        .write_all(&protocol::reply(REP_SUCCESS, listen_addr))
+        .await?;
+
+    let (mut reader, mut writer) = tokio::io::split(tunnel);
+    // 与 UDPRelay 一致：客户端声明了端口时直接锁定，否则锁定该 IP 发来的第一个报文的源地址
+    let client_ip = client.ip().to_canonical();
+    let client_addr = OnceLock::new();
+    if client.port() != 0 {
+        let _ = client_addr.set(SocketAddr::new(client_ip, client.port()));
+    }
+
+    let uplink = async {
+        let mut buf = vec![0u8; MAX_UDP_SIZE as usize];
+        loop {
+            let (len, src_addr) = udp.recv_from(&mut buf).await?;
+            let src_addr = SocketAddr::new(src_addr.ip().to_canonical(), src_addr.port());
+            if src_addr.ip() != client_ip {
+                continue;
+            }
+            if *client_addr.get_or_init(|| src_addr) != src_addr {
+                continue;
+            }
+            write_frame(&mut writer, &buf[..len]).await?;
+        }
+    };
+
+    let downlink = async {
+        let mut buf = vec![0u8; MAX_UDP_SIZE as usize];
+        while let Some(len) = read_frame(&mut reader, &mut buf).await? {
+            if let Some(client) = client_addr.get() {
+                udp.send_to(&buf[..len], client).await?;
+            }
+        }
+        debug!("remote closed udp tunnel");
+        Ok(())
+    };
+
+    // SOCKS5 规定：当 TCP 断开时，UDP 关联也必须停止
+    let mut keepalive_buf = [0u8; 1];
+    tokio::select! {
+        res = uplink => res,
+        res = downlink => res,
+        res = socket.read(&mut keepalive_buf) => {
+            match res {
+                Ok(0) => debug!("Client closed TCP connection, stopping UDP"),
+                Ok(_) => warn!("Unexpected data on TCP control channel"),
+                Err(e) => warn!("TCP connection error: {}", e),
+            }
+            Ok(())
+        }
+    }
+}
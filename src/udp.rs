@@ -1,80 +1,142 @@
-use std::error::Error;
-use std::net::SocketAddr;
-use std::result;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::UdpSocket;
-use tokio::time::timeout;
+use tokio::sync::{Semaphore, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 use tracing::{debug, error, warn};
 
+use crate::auth::UserConfig;
+use crate::batch::{self, BATCH};
+use crate::config::{UdpConfig, UdpFilter};
 use crate::consts::*;
-use crate::protocol::{Address, UDPAssociateHeader};
+use crate::dns::{DNS_PORT, Dns};
+use crate::error::SocksError;
+use crate::limits::TokenBucket;
+use crate::metrics::{self, UdpDrop};
+use crate::ports::RelaySocket;
+use crate::protocol::{Address, SocksRequest, UDPAssociateHeader};
+use crate::rules::Rules;
+use crate::transport::Stream;
+
+/// 清理过期 NAT 会话的间隔
+const NAT_CLEANUP_INTERVAL: Duration = Duration::from_secs(10);
+/// 出站 socket 收到的回包在送回客户端前的排队上限
+const NAT_QUEUE: usize = 256;
+/// 分片重组计时器 (RFC 1928 要求不少于 5 秒)
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// 单个 UDP 关联同时重组的目标数上限
+const REASSEMBLY_MAX_QUEUES: usize = 16;
+/// 单个 UDP 关联所有重组队列缓存的字节数上限
+const REASSEMBLY_MAX_BYTES: usize = 256 * 1024;
+/// 单个 UDP 关联同时在途的拦截 DNS 查询数上限
+const DNS_MAX_INFLIGHT: usize = 16;
 
 pub struct UDPRelay {
-    socket: Arc<UdpSocket>,
-    client_addr: Option<SocketAddr>,      // 记录 Client 的 UDP 地址
-    expected_client_ip: std::net::IpAddr, // 握手时记录的 Client IP，用于安全校验
+    socket: RelaySocket,             // 面向客户端的 socket，只收发客户端的报文
+    client_addr: Option<SocketAddr>, // 记录 Client 的 UDP 地址
+    expected_client: SocketAddr,     // 允许的 Client 地址，端口为 0 表示不限，用于安全校验
+    idle_timeout: Duration,
+    nat: Nat,
 }
 
 impl UDPRelay {
-    pub async fn new(client_ip: std::net::IpAddr) -> Result<(Self, SocketAddr), Box<dyn Error>> {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    /// `expected_client` 见 [`expected_client`]，端口非 0 时直接锁定该地址
+    pub async fn new(
+        expected_client: SocketAddr,
+        config: &UdpConfig,
+        shared: &UserConfig,
+    ) -> io::Result<(Self, SocketAddr)> {
+        let socket = shared.udp_ports.bind(expected_client).await?;
         let listen_addr = socket.local_addr()?;
+        let expected_client =
+            SocketAddr::new(expected_client.ip().to_canonical(), expected_client.port());
 
         Ok((
             UDPRelay {
-                socket: Arc::new(socket),
-                client_addr: None,
-                expected_client_ip: client_ip,
+                socket,
+                client_addr: (expected_client.port() != 0).then_some(expected_client),
+                expected_client,
+                idle_timeout: Duration::from_secs(config.idle_timeout),
+                nat: Nat::new(config, shared),
             },
             listen_addr,
         ))
     }
 
-    pub async fn run(mut self) -> Result<(), Box<dyn Error>> {
-        let mut buf = [0u8; MAX_UDP_SIZE as usize];
+    pub async fn run(mut self) -> Result<(), SocksError> {
+        let idle = self.idle_timeout;
+        let mut deadline = Instant::now() + idle;
+        let mut cleanup = tokio::time::interval(NAT_CLEANUP_INTERVAL);
+
         loop {
-            let res = timeout(
-                Duration::from_secs(UDP_TIMEOUT as u64),
-                self.socket.recv_from(&mut buf),
-            )
-            .await;
-
-            let (len, src_addr) = match res {
-                Ok(Ok(result)) => result,
-                Ok(Err(e)) => {
-                    error!("udp read error:{}", e);
-                    continue;
+            tokio::select! {
+                res = self.socket.recv() => {
+                    let batch = match res {
+                        Ok(batch) => batch,
+                        Err(e) => {
+                            error!("udp read error:{}", e);
+                            continue;
+                        }
+                    };
+                    for (packet, src_addr) in batch.iter() {
+                        if !self.is_from_client(&src_addr) {
+                            debug!("drop udp packet from non-client {}", src_addr);
+                            metrics::udp_drop(UdpDrop::NonClient);
+                            continue;
+                        }
+                        deadline = Instant::now() + idle;
+                        // 来自客户端 -> 发往目标
+                        if let Err(e) = self.handle_outbound(packet, src_addr).await {
+                            debug!("handle outbound error: {}", e);
+                        }
+                    }
+                }
+                Some(reply) = self.nat.recv() => {
+                    // 顺带取出已排队的回包，一次批量发回
+                    let mut packets = Vec::new();
+                    let mut next = Some(reply);
+                    while let Some(reply) = next.take() {
+                        if self.nat.accept(&reply) {
+                            if reply.is_solicited() {
+                                deadline = Instant::now() + idle;
+                            }
+                            packets.push(encapsulate(reply.src, &reply.data));
+                        }
+                        if packets.len() < BATCH {
+                            next = self.nat.try_recv();
+                        }
+                    }
+                    // 来自目标 -> 发回客户端
+                    if let Err(e) = self.handle_inbound(&packets).await {
+                        debug!("handle inbound error: {}", e);
+                    }
                 }
-                Err(_) => {
+                _ = cleanup.tick() => self.nat.expire(),
+                _ = tokio::time::sleep_until(deadline) => {
                     debug!("udp timeout, closed");
                     return Ok(());
                 }
-            };
-
-            if self.is_from_client(&src_addr) {
-                // 来自客户端 -> 发往目标
-                if let Err(e) = self.handle_outbound(&buf[..len], src_addr).await {
-                    debug!("handle outbound error: {}", e);
-                }
-            } else {
-                // 来自目标 -> 发回客户端
-                if let Err(e) = self.handle_inbound(&buf[..len], src_addr).await {
-                    debug!("handle inbound error: {}", e);
-                }
             }
         }
     }
 
     fn is_from_client(&self, addr: &SocketAddr) -> bool {
-        // 1. IP 必须匹配握手时的 IP
-        if addr.ip() != self.expected_client_ip {
+        let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+
+        // 1. IP 必须匹配客户端声明的 IP (未声明时为握手时的 IP)
+        if addr.ip() != self.expected_client.ip() {
             return false;
         }
 
-        // 2. 如果我们已经记录了 Client 的完整地址 (IP+Port)，则直接匹配
+        // 2. 如果客户端声明了端口，或我们已经记录了 Client 的完整地址 (IP+Port)，则直接匹配
         if let Some(client) = self.client_addr {
-            return client == *addr;
+            return client == addr;
         }
 
         // 3. 如果是第一次收到该 IP 的包，我们默认它就是 Client，并记录 Port
@@ -86,55 +148,589 @@ impl UDPRelay {
         &mut self,
         packet: &[u8],
         src_addr: SocketAddr,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), SocksError> {
         if self.client_addr.is_none() {
             debug!("lock udp client:{}", src_addr);
             self.client_addr = Some(src_addr);
         }
 
-        let (header, header_len) = UDPAssociateHeader::parse(packet)?;
+        self.nat.send(packet).await
+    }
 
-        if header.frag != 0 {
-            warn!("unsupport UDP frag...");
+    async fn handle_inbound(&self, packets: &[Vec<u8>]) -> Result<(), SocksError> {
+        if packets.is_empty() {
             return Ok(());
         }
+        let client_addr = match self.client_addr {
+            Some(addr) => addr,
+            None => return Err(SocksError::Protocol("unkonw client addr".into())),
+        };
 
-        let payload = &packet[header_len..];
+        // 发回 Client (已封装好 SOCKS5 UDP 头)
+        self.socket.send(client_addr, packets).await?;
 
-        let target_addr = format!("{:?}:{:?}", header.address, header.port);
-        self.socket.send_to(payload, target_addr).await?;
         Ok(())
     }
+}
 
-    async fn handle_inbound(
-        &self,
-        payload: &[u8],
-        src_addr: SocketAddr,
-    ) -> Result<(), Box<dyn Error>> {
-        let client_addr = match self.client_addr {
-            Some(addr) => addr,
-            None => return Err("unkonw client addr".into()),
+/// UDP ASSOCIATE 请求中的 DST.ADDR / DST.PORT 是客户端将用来发送 UDP 的地址
+///
+/// 位于 NAT 之后的客户端不知道自己的外部地址，会发送全 0：
+/// 地址为 0 (或域名) 时以 TCP 连接的来源 IP 为准，端口为 0 时不限端口，锁定第一个报文的来源
+pub fn expected_client(request: &SocksRequest, peer_addr: SocketAddr) -> SocketAddr {
+    let ip = match request.address.to_socket_addr(request.port) {
+        Some(addr) if !addr.ip().is_unspecified() => addr.ip(),
+        _ => peer_addr.ip(),
+    };
+    SocketAddr::new(ip, request.port)
+}
+
+/// 按客户端的地址族绑定面向客户端的 UDP socket
+///
+/// 双栈监听下 IPv4 客户端以映射地址 (::ffff:a.b.c.d) 出现，按 IPv4 处理
+pub async fn bind_relay(client_ip: IpAddr) -> io::Result<UdpSocket> {
+    let unspecified = match client_ip.to_canonical() {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    UdpSocket::bind(SocketAddr::new(unspecified, 0)).await
+}
+
+/// 流模式：客户端一侧不是 UDP，而是按帧承载 SOCKS5 UDP 报文的字节流 (如加密隧道)
+///
+/// 流上读到的每一帧经 NAT 发往目标，目标的回包封装后按帧写回；流关闭或超时后结束
+pub async fn run_stream<S: Stream>(
+    stream: S,
+    config: &UdpConfig,
+    shared: &UserConfig,
+) -> Result<(), SocksError> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let (client_tx, mut client_rx) = mpsc::channel::<Vec<u8>>(NAT_QUEUE);
+
+    // 读帧不能被 select 取消，单独放在一个 future 里
+    let uplink = async {
+        let mut buf = vec![0u8; MAX_UDP_SIZE as usize];
+        while let Some(len) = read_frame(&mut reader, &mut buf).await? {
+            if client_tx.send(buf[..len].to_vec()).await.is_err() {
+                break;
+            }
+        }
+        debug!("udp stream closed");
+        Ok(())
+    };
+
+    let relay = async {
+        let mut nat = Nat::new(config, shared);
+        let idle = Duration::from_secs(config.idle_timeout);
+        let mut deadline = Instant::now() + idle;
+        let mut cleanup = tokio::time::interval(NAT_CLEANUP_INTERVAL);
+
+        loop {
+            tokio::select! {
+                Some(packet) = client_rx.recv() => {
+                    deadline = Instant::now() + idle;
+                    if let Err(e) = nat.send(&packet).await {
+                        debug!("handle outbound error: {}", e);
+                    }
+                }
+                Some(reply) = nat.recv() => {
+                    if !nat.accept(&reply) {
+                        continue;
+                    }
+                    if reply.is_solicited() {
+                        deadline = Instant::now() + idle;
+                    }
+                    let packet = encapsulate(reply.src, &reply.data);
+                    if packet.len() > u16::MAX as usize {
+                        debug!("drop oversized udp reply from {}", reply.src);
+                        continue;
+                    }
+                    write_frame(&mut writer, &packet).await?;
+                }
+                _ = cleanup.tick() => nat.expire(),
+                _ = tokio::time::sleep_until(deadline) => {
+                    debug!("udp timeout, closed");
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    tokio::select! {
+        res = uplink => res,
+        res = relay => res,
+    }
+}
+
+/// 出站 socket 收到的报文
+struct Inbound {
+    target: SocketAddr, // 该出站 socket 对应的目标
+    src: SocketAddr,    // 报文实际的来源
+    data: Vec<u8>,
+    local: bool, // 由代理自己生成 (如拦截的 DNS 应答)，不经过 NAT 会话
+}
+
+impl Inbound {
+    /// 回包是否来自该会话的目标本身；其他来源 (full-cone 等策略放行的) 不刷新关联的空闲计时
+    fn is_solicited(&self) -> bool {
+        self.src == self.target
+    }
+}
+
+/// 一个目标对应的出站 socket
+struct NatSession {
+    socket: Arc<UdpSocket>,
+    last_active: Instant,
+    task: JoinHandle<()>,
+}
+
+impl Drop for NatSession {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// UDP 关联的出站 NAT 表
+///
+/// 每个目标地址使用独立的出站 socket，与面向客户端的 socket 分开；
+/// 回包按过滤策略检查后才会送回客户端，会话空闲超时后关闭其 socket。
+/// 目标数、报文大小和每个方向的报文速率按配置限制
+struct Nat {
+    sessions: HashMap<SocketAddr, NatSession>,
+    domains: HashMap<String, SocketAddr>, // 域名目标的解析结果，随会话一起过期
+    tx: mpsc::Sender<Inbound>,
+    rx: mpsc::Receiver<Inbound>,
+    filter: UdpFilter,
+    session_timeout: Duration,
+    max_destinations: usize,
+    max_datagram_size: usize,
+    rate_out: TokenBucket, // 客户端 -> 目标
+    rate_in: TokenBucket,  // 目标 -> 客户端
+    reassembler: Reassembler,
+    rules: Arc<Rules>,
+    dns: Arc<Dns>,
+    dns_inflight: Arc<Semaphore>,
+}
+
+impl Nat {
+    fn new(config: &UdpConfig, shared: &UserConfig) -> Self {
+        let (tx, rx) = mpsc::channel(NAT_QUEUE);
+        Nat {
+            sessions: HashMap::new(),
+            domains: HashMap::new(),
+            tx,
+            rx,
+            filter: config.filter,
+            session_timeout: Duration::from_secs(config.session_timeout),
+            max_destinations: config.max_destinations,
+            max_datagram_size: config.max_datagram_size,
+            rate_out: TokenBucket::new(config.rate_limit),
+            rate_in: TokenBucket::new(config.rate_limit),
+            reassembler: Reassembler::default(),
+            rules: shared.rules.clone(),
+            dns: shared.dns.clone(),
+            dns_inflight: Arc::new(Semaphore::new(DNS_MAX_INFLIGHT)),
+        }
+    }
+
+    /// 解析 SOCKS5 UDP 头，用目标对应的出站 socket 发出负载
+    async fn send(&mut self, packet: &[u8]) -> Result<(), SocksError> {
+        if !self.rate_out.take() {
+            metrics::udp_drop(UdpDrop::RateLimited);
+            return Ok(());
+        }
+        let (header, header_len) = UDPAssociateHeader::parse(packet).inspect_err(|_| {
+            metrics::udp_drop(UdpDrop::Malformed);
+        })?;
+        let key = header.to_string();
+
+        let payload = &packet[header_len..];
+        let reassembled;
+        let payload = if header.frag == 0 {
+            // 独立报文，同时丢弃该目标未完成的重组队列
+            self.reassembler.reset(&key);
+            payload
+        } else {
+            match self.reassembler.push(&key, header.frag, payload) {
+                Some(data) => {
+                    reassembled = data;
+                    &reassembled[..]
+                }
+                None => return Ok(()),
+            }
         };
 
-        let address = match src_addr.ip() {
-            std::net::IpAddr::V4(ip) => Address::IpV4(ip),
-            std::net::IpAddr::V6(ip) => Address::IpV6(ip),
+        if payload.len() > self.max_datagram_size {
+            debug!(
+                "drop oversized udp datagram to {} ({}b)",
+                header,
+                payload.len()
+            );
+            metrics::udp_drop(UdpDrop::Oversized);
+            return Ok(());
+        }
+
+        if let Address::Domain(domain) = &header.address
+            && self.rules.is_blocked(domain)
+        {
+            debug!("udp destination blocked: {}", domain);
+            metrics::udp_drop(UdpDrop::Blocked);
+            return Ok(());
+        }
+
+        let target = self.resolve(&header, key).await?;
+        if target.port() == DNS_PORT && self.dns.intercept() {
+            self.query_dns(payload.to_vec(), target);
+            return Ok(());
+        }
+
+        if self.max_destinations != 0
+            && self.sessions.len() >= self.max_destinations
+            && !self.sessions.contains_key(&target)
+        {
+            debug!("udp destination limit reached, drop datagram to {}", target);
+            metrics::udp_drop(UdpDrop::Destinations);
+            return Ok(());
+        }
+
+        let session = match self.sessions.entry(target) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let session = open_session(target, self.tx.clone()).await?;
+                debug!("new udp nat session: {}", target);
+                entry.insert(session)
+            }
         };
+        session.last_active = Instant::now();
+        session.socket.send_to(payload, target).await?;
+        Ok(())
+    }
+
+    /// IP 目标直接使用；域名目标在会话存活期间复用同一个解析结果，
+    /// 避免每个报文都查询一次 DNS，也保证回包来自同一个地址
+    async fn resolve(
+        &mut self,
+        header: &UDPAssociateHeader,
+        key: String,
+    ) -> Result<SocketAddr, SocksError> {
+        if let Some(addr) = header.address.to_socket_addr(header.port) {
+            return Ok(addr);
+        }
+        if let Some(addr) = self.domains.get(&key)
+            && self.sessions.contains_key(addr)
+        {
+            return Ok(*addr);
+        }
+        let ip = match &header.address {
+            Address::Domain(domain) => self
+                .dns
+                .lookup(domain)
+                .await
+                .map_err(|e| SocksError::Dns(key.clone(), e))?
+                .into_iter()
+                .next(),
+            _ => None,
+        }
+        .ok_or_else(|| SocksError::Dns(key.clone(), io::ErrorKind::NotFound.into()))?;
+        let addr = SocketAddr::new(ip, header.port);
+        debug!("udp resolved {} -> {}", key, addr);
+        self.domains.insert(key, addr);
+        Ok(addr)
+    }
 
-        let header = UDPAssociateHeader {
-            frag: 0,
-            address,
-            port: src_addr.port(),
+    /// 拦截的 DNS 查询在后台处理，应答作为来自原目标的回包送回客户端；
+    /// 在途查询达到上限时直接丢弃，客户端会自行重试
+    fn query_dns(&self, query: Vec<u8>, server: SocketAddr) {
+        let Ok(permit) = self.dns_inflight.clone().try_acquire_owned() else {
+            debug!("drop dns query to {}: too many in flight", server);
+            metrics::udp_drop(UdpDrop::DnsQueue);
+            return;
         };
+        let dns = self.dns.clone();
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            let Some(data) = dns.handle(&query, server).await else {
+                debug!("drop malformed dns query to {}", server);
+                metrics::udp_drop(UdpDrop::Malformed);
+                return;
+            };
+            let reply = Inbound {
+                target: server,
+                src: server,
+                data,
+                local: true,
+            };
+            let _ = tx.send(reply).await;
+        });
+    }
 
-        // 2. 序列化 Header + Payload
-        let mut send_buf = Vec::with_capacity(20 + payload.len());
-        header.write(&mut send_buf);
-        send_buf.extend_from_slice(payload);
+    async fn recv(&mut self) -> Option<Inbound> {
+        self.rx.recv().await
+    }
 
-        // 3. 发回 Client
-        self.socket.send_to(&send_buf, client_addr).await?;
+    fn try_recv(&mut self) -> Option<Inbound> {
+        self.rx.try_recv().ok()
+    }
 
-        Ok(())
+    /// 按过滤策略检查回包，通过时刷新对应会话
+    fn accept(&mut self, reply: &Inbound) -> bool {
+        if reply.local {
+            return self.rate_in.take();
+        }
+        let Some(session) = self.sessions.get_mut(&reply.target) else {
+            return false;
+        };
+        let allowed = match self.filter {
+            UdpFilter::FullCone => true,
+            UdpFilter::AddressRestricted => reply.src.ip() == reply.target.ip(),
+            UdpFilter::PortRestricted => reply.src == reply.target,
+        };
+        if !allowed {
+            debug!(
+                "drop udp reply from {} (session {})",
+                reply.src, reply.target
+            );
+            metrics::udp_drop(UdpDrop::Filtered);
+            return false;
+        }
+        if !self.rate_in.take() {
+            metrics::udp_drop(UdpDrop::RateLimited);
+            return false;
+        }
+        if reply.is_solicited() {
+            session.last_active = Instant::now();
+        }
+        true
+    }
+
+    fn expire(&mut self) {
+        self.reassembler.expire();
+
+        let timeout = self.session_timeout;
+        self.sessions.retain(|target, session| {
+            let alive = session.last_active.elapsed() < timeout;
+            if !alive {
+                debug!("udp nat session expired: {}", target);
+            }
+            alive
+        });
+        let sessions = &self.sessions;
+        self.domains.retain(|_, addr| sessions.contains_key(addr));
+    }
+}
+
+/// 一个目标正在重组的分片
+struct FragQueue {
+    fragments: Vec<Vec<u8>>,
+    bytes: usize,
+    started: Instant,
+}
+
+/// RFC 1928 UDP 分片重组
+///
+/// FRAG 为 1~127 的分片位置，最高位表示这是序列的最后一片。
+/// 分片必须按位置依次到达，出现回退或缺片时丢弃整个队列；
+/// 计时器超时、收到 FRAG=0 的报文或超出内存上限时同样丢弃
+#[derive(Default)]
+struct Reassembler {
+    queues: HashMap<String, FragQueue>,
+    bytes: usize,
+}
+
+impl Reassembler {
+    /// 加入一个分片，序列完整时返回重组后的负载
+    fn push(&mut self, key: &str, frag: u8, data: &[u8]) -> Option<Vec<u8>> {
+        let position = (frag & 0x7F) as usize;
+        let is_last = frag & 0x80 != 0;
+        if position == 0 {
+            debug!("invalid udp fragment 0x{:02x} to {}", frag, key);
+            self.reset(key);
+            metrics::udp_drop(UdpDrop::Fragment);
+            return None;
+        }
+
+        if self
+            .queues
+            .get(key)
+            .is_some_and(|q| q.started.elapsed() >= REASSEMBLY_TIMEOUT)
+        {
+            debug!("udp reassembly timeout: {}", key);
+            self.reset(key);
+        }
+
+        let expected = self.queues.get(key).map_or(1, |q| q.fragments.len() + 1);
+        if position != expected {
+            debug!(
+                "out of order udp fragment {} (expected {}) to {}",
+                position, expected, key
+            );
+            self.reset(key);
+            if position != 1 {
+                metrics::udp_drop(UdpDrop::Fragment);
+                return None;
+            }
+        }
+
+        if !self.queues.contains_key(key) && self.queues.len() >= REASSEMBLY_MAX_QUEUES {
+            warn!("too many udp reassembly queues, drop fragment to {}", key);
+            metrics::udp_drop(UdpDrop::Fragment);
+            return None;
+        }
+        let queue_bytes = self.queues.get(key).map_or(0, |q| q.bytes);
+        if queue_bytes + data.len() > MAX_UDP_SIZE as usize
+            || self.bytes + data.len() > REASSEMBLY_MAX_BYTES
+        {
+            warn!("udp reassembly buffer full, drop fragments to {}", key);
+            self.reset(key);
+            metrics::udp_drop(UdpDrop::Fragment);
+            return None;
+        }
+
+        let queue = self
+            .queues
+            .entry(key.to_string())
+            .or_insert_with(|| FragQueue {
+                fragments: Vec::new(),
+                bytes: 0,
+                started: Instant::now(),
+            });
+        queue.fragments.push(data.to_vec());
+        queue.bytes += data.len();
+        self.bytes += data.len();
+
+        if !is_last {
+            return None;
+        }
+        let queue = self.queues.remove(key)?;
+        self.bytes -= queue.bytes;
+        debug!(
+            "udp reassembled {} fragments ({}b) to {}",
+            queue.fragments.len(),
+            queue.bytes,
+            key
+        );
+        Some(queue.fragments.concat())
     }
+
+    /// 丢弃该目标未完成的分片序列
+    fn reset(&mut self, key: &str) {
+        if let Some(queue) = self.queues.remove(key) {
+            self.bytes -= queue.bytes;
+            metrics::udp_drop(UdpDrop::Fragment);
+        }
+    }
+
+    fn expire(&mut self) {
+        let bytes = &mut self.bytes;
+        self.queues.retain(|_, queue| {
+            let alive = queue.started.elapsed() < REASSEMBLY_TIMEOUT;
+            if !alive {
+                *bytes -= queue.bytes;
+                metrics::udp_drop(UdpDrop::Fragment);
+            }
+            alive
+        });
+    }
+}
+
+async fn open_session(target: SocketAddr, tx: mpsc::Sender<Inbound>) -> io::Result<NatSession> {
+    let bind_addr = if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = Arc::new(UdpSocket::bind(bind_addr).await?);
+    batch::enable_gro(&socket);
+
+    let reader = socket.clone();
+    let task = tokio::spawn(async move {
+        loop {
+            // 先拷出报文再归还缓冲，排队等待时不占用缓冲池
+            let inbound: Vec<_> = match batch::recv(&reader).await {
+                Ok(batch) => batch
+                    .iter()
+                    .map(|(data, src)| Inbound {
+                        target,
+                        src,
+                        data: data.to_vec(),
+                        local: false,
+                    })
+                    .collect(),
+                Err(e) => {
+                    debug!("udp nat read error ({}): {}", target, e);
+                    continue;
+                }
+            };
+            for inbound in inbound {
+                if tx.send(inbound).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(NatSession {
+        socket,
+        last_active: Instant::now(),
+        task,
+    })
+}
+
+/// 为目标的回包加上 SOCKS5 UDP 头
+fn encapsulate(src_addr: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let header = UDPAssociateHeader {
+        frag: 0,
+        address: Address::from(src_addr.ip()),
+        port: src_addr.port(),
+    };
+
+    // 序列化 Header + Payload
+    let mut send_buf = Vec::with_capacity(22 + payload.len());
+    header.write(&mut send_buf);
+    send_buf.extend_from_slice(payload);
+    send_buf
+}
+
+/// UDP over TCP 帧
+/// +-----+----------------------------+
+/// | LEN |  SOCKS5 UDP 报文 (头 + 数据) |
+/// +-----+----------------------------+
+/// |  2  |          LEN               |
+/// +-----+----------------------------+
+///
+/// 读取一帧到 buf，返回长度；在帧边界上遇到 EOF 时返回 None
+pub async fn read_frame<R>(reader: &mut R, buf: &mut [u8]) -> io::Result<Option<usize>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut len_buf = [0u8; 2];
+    match reader.read_exact(&mut len_buf).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u16::from_be_bytes(len_buf) as usize;
+    if len > buf.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "udp frame too large",
+        ));
+    }
+    reader.read_exact(&mut buf[..len]).await?;
+    Ok(Some(len))
+}
+
+/// 写入一帧并 flush
+pub async fn write_frame<W>(writer: &mut W, packet: &[u8]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let len = u16::try_from(packet.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "udp frame too large"))?;
+    let mut frame = Vec::with_capacity(2 + packet.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(packet);
+    writer.write_all(&frame).await?;
+    writer.flush().await
 }
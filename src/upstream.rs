@@ -0,0 +1,83 @@
+use std::error::Error;
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use crate::client;
+use crate::consts::*;
+use crate::error::SocksError;
+use crate::protocol::{self, Address, SocksRequest};
+
+/// 上游 SOCKS5 代理：`socks5://[user:pass@]host:port`
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    pub address: Address,
+    pub port: u16,
+    pub auth: Option<(String, String)>,
+}
+
+impl Upstream {
+    pub fn parse(url: &str) -> Result<Self, Box<dyn Error>> {
+        let rest = url
+            .strip_prefix("socks5://")
+            .ok_or_else(|| format!("upstream must be socks5://: {}", url))?;
+        let (auth, host) = match rest.rsplit_once('@') {
+            Some((auth, host)) => {
+                let (user, pass) = auth
+                    .split_once(':')
+                    .ok_or_else(|| format!("upstream auth must be user:pass: {}", url))?;
+                if user.len() > 255 || pass.len() > 255 {
+                    return Err(format!("upstream credentials too long: {}", url).into());
+                }
+                (Some((user.to_string(), pass.to_string())), host)
+            }
+            None => (None, rest),
+        };
+        let (address, port) = protocol::parse_target(host)
+            .ok_or_else(|| format!("invalid upstream address: {}", url))?;
+        Ok(Upstream {
+            address,
+            port,
+            auth,
+        })
+    }
+
+    /// 第一跳的连接目标
+    pub fn request(&self) -> SocksRequest {
+        SocksRequest {
+            cmd: CMD_CONNECT,
+            address: self.address.clone(),
+            port: self.port,
+        }
+    }
+}
+
+/// 在已连上 `chain[0]` 的 `stream` 上逐跳 CONNECT，最后一跳连接 `target`；
+/// 某一跳的失败应答原样作为 [`SocksError::Upstream`] 返回
+pub async fn connect_chain(
+    stream: &mut TcpStream,
+    chain: &[Upstream],
+    target: &SocksRequest,
+) -> Result<(), SocksError> {
+    for (i, hop) in chain.iter().enumerate() {
+        let next = match chain.get(i + 1) {
+            Some(next) => next.request(),
+            None => SocksRequest {
+                cmd: CMD_CONNECT,
+                address: target.address.clone(),
+                port: target.port,
+            },
+        };
+        debug!("upstream {} CONNECT {}", hop.request(), next);
+        let auth = hop.auth.as_ref().map(|(u, p)| (u.as_str(), p.as_str()));
+        // 上游拒绝认证不是客户端自己的认证失败，按规则不允许应答
+        client::negotiate(stream, auth).await.map_err(|e| match e {
+            SocksError::NoAcceptableMethod | SocksError::AuthFailed(_) => SocksError::Upstream(
+                format!("upstream {} authentication", hop.request()),
+                REP_CONNECTION_NOT_ALLOWED,
+            ),
+            e => e,
+        })?;
+        client::request(stream, &next).await?;
+    }
+    Ok(())
+}
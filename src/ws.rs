@@ -0,0 +1,208 @@
+use futures_util::{Sink, Stream as _};
+use std::error::Error;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::{self, Bytes, Message};
+use tracing::{debug, error, info, warn};
+
+use crate::auth::UserConfig;
+use crate::handler;
+use crate::idle;
+use crate::proxy_protocol;
+use crate::transport::Stream;
+
+/// 把 WebSocket 消息流适配成字节流
+///
+/// 写入的数据以 Binary 帧发送；读取时拼接收到的 Binary 帧，忽略 Ping/Pong/Text，Close 视为 EOF
+pub struct WsStream<S> {
+    inner: WebSocketStream<S>,
+    read_buf: Bytes,
+}
+
+impl<S> WsStream<S> {
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        WsStream {
+            inner,
+            read_buf: Bytes::new(),
+        }
+    }
+}
+
+fn to_io_error(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if !self.read_buf.is_empty() {
+                let n = self.read_buf.len().min(buf.remaining());
+                let chunk = self.read_buf.split_to(n);
+                buf.put_slice(&chunk);
+                return Poll::Ready(Ok(()));
+            }
+
+            match ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => self.read_buf = data,
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Ok(_)) => continue,
+                Some(Err(tungstenite::Error::ConnectionClosed)) => return Poll::Ready(Ok(())),
+                Some(Err(e)) => return Poll::Ready(Err(to_io_error(e))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(Pin::new(&mut self.inner).poll_ready(cx)).map_err(to_io_error)?;
+        Pin::new(&mut self.inner)
+            .start_send(Message::Binary(Bytes::copy_from_slice(buf)))
+            .map_err(to_io_error)?;
+        // SOCKS5 流程写完应答后不会主动 flush，这里立即尝试发出，
+        // Pending 时帧已在缓冲区中，后续的读写会继续推进发送
+        if let Poll::Ready(Err(e)) = Pin::new(&mut self.inner).poll_flush(cx) {
+            return Poll::Ready(Err(to_io_error(e)));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner)
+            .poll_flush(cx)
+            .map_err(to_io_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match ready!(Pin::new(&mut self.inner).poll_close(cx)) {
+            Ok(()) | Err(tungstenite::Error::ConnectionClosed) => Poll::Ready(Ok(())),
+            Err(e) => Poll::Ready(Err(to_io_error(e))),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for WsStream<S> {}
+
+/// 服务端：接受 WebSocket 升级，并在消息流上跑普通的 SOCKS5 流程
+pub async fn serve(
+    listener: TcpListener,
+    path: String,
+    config: Arc<UserConfig>,
+) -> Result<(), Box<dyn Error>> {
+    info!(
+        "WebSocket listener running on {} (path {})",
+        listener.local_addr()?,
+        path
+    );
+    let path = Arc::new(path);
+
+    loop {
+        let (mut socket, addr) = listener.accept().await?;
+        let config_clone = config.clone();
+        let path = path.clone();
+
+        tokio::spawn(async move {
+            let addr = match proxy_protocol::accept(&mut socket, addr, &config_clone).await {
+                Ok(addr) => addr,
+                Err(e) => {
+                    warn!("PROXY header from {:?} rejected: {}", addr, e);
+                    return;
+                }
+            };
+            // HTTP 升级是客户端发来的第一段数据，与方法协商共用时限
+            let upgrade = async {
+                let ws = accept(socket, &path).await;
+                ws.map_err(|e| io::Error::other(e.to_string()).into())
+            };
+            let greeting = config_clone.timeouts.greeting;
+            let ws = match handler::within(greeting, "greeting", upgrade).await {
+                Ok(ws) => ws,
+                Err(e) => {
+                    warn!("WebSocket handshake from {:?} failed: {}", addr, e);
+                    return;
+                }
+            };
+            if let Err(e) = handler::process(ws, addr, config_clone.as_ref()).await {
+                error!("[Error] from {:?} (ws) [{}] : {}", addr, e.label(), e);
+            }
+        });
+    }
+}
+
+async fn accept(socket: TcpStream, path: &str) -> Result<WsStream<TcpStream>, Box<dyn Error>> {
+    // 回调签名由 tungstenite 规定
+    #[allow(clippy::result_large_err)]
+    let check_path = |req: &Request, resp: Response| -> Result<Response, ErrorResponse> {
+        if req.uri().path() == path {
+            Ok(resp)
+        } else {
+            let mut err = ErrorResponse::new(None);
+            *err.status_mut() = StatusCode::NOT_FOUND;
+            Err(err)
+        }
+    };
+    let ws = tokio_tungstenite::accept_hdr_async(socket, check_path).await?;
+    Ok(WsStream::new(ws))
+}
+
+/// 本地客户端：暴露普通 SOCKS5 端口，每个会话都经 WebSocket 原样转发到服务端
+///
+/// 握手、认证和请求都由服务端处理，本地只搬运字节；两个方向都超过 `timeouts.idle` 没有数据时关闭
+pub async fn run_local(
+    listener: TcpListener,
+    server: String,
+    config: Arc<UserConfig>,
+) -> Result<(), Box<dyn Error>> {
+    info!(
+        "Local SOCKS5 running on {}, tunneling to {}",
+        listener.local_addr()?,
+        server
+    );
+    let server = Arc::new(server);
+    let idle = Duration::from_secs(config.timeouts.idle);
+
+    loop {
+        let (mut socket, addr) = listener.accept().await?;
+        let server = server.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = tunnel(&mut socket, &server, idle).await {
+                error!("[Error] tunnel from {:?} : {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn tunnel(
+    socket: &mut TcpStream,
+    server: &str,
+    idle: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let (ws, _) = tokio_tungstenite::connect_async_with_config(server, None, true).await?;
+    let mut ws = WsStream::new(ws);
+
+    match idle::copy(socket, &mut ws, idle).await? {
+        Some((up, down)) => debug!("WebSocket 隧道结束: 上行 {}b, 下行 {}b", up, down),
+        None => info!("WebSocket 隧道空闲超过 {}s，关闭", idle.as_secs()),
+    }
+    Ok(())
+}
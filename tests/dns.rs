@@ -0,0 +1,148 @@
+//! DNS 拦截：客户端各自指定的服务器互不影响，不匹配查询的应答被丢弃，在途查询数有上限
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::time::Instant;
+
+use proxy::client::Client;
+use proxy::config::{DnsConfig, RulesConfig};
+use proxy::dns::Dns;
+use proxy::metrics::{UdpDrop, udp_drops};
+use proxy::rules::Rules;
+use proxy::{Address, Server};
+
+/// 只拦截、不配置上游：查询发往客户端数据报里的服务器
+fn dns() -> Dns {
+    let config = DnsConfig {
+        intercept: true,
+        ..DnsConfig::default()
+    };
+    let rules = Arc::new(Rules::new(&RulesConfig::default()).unwrap());
+    Dns::new(&config, rules).unwrap()
+}
+
+/// 单问题的 A 查询
+fn query(id: u16, name: &str) -> Vec<u8> {
+    let mut msg = id.to_be_bytes().to_vec();
+    msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.extend_from_slice(&[0, 0, 1, 0, 1]);
+    msg
+}
+
+/// 对查询 (不含附加段) 的应答：一条 TTL 300 的 A 记录
+fn answer(query: &[u8], ip: Ipv4Addr) -> Vec<u8> {
+    let mut msg = query.to_vec();
+    msg[2] = 0x81;
+    msg[3] = 0x80;
+    msg[6..8].copy_from_slice(&1u16.to_be_bytes());
+    msg.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1]);
+    msg.extend_from_slice(&300u32.to_be_bytes());
+    msg.extend_from_slice(&4u16.to_be_bytes());
+    msg.extend_from_slice(&ip.octets());
+    msg
+}
+
+/// 假的 DNS 服务器，所有 A 查询都回答 `ip`；`spoof` 时对第一个查询先发一个 ID 相同、问题不同的应答
+async fn fake_server(ip: Ipv4Addr, mut spoof: bool) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+            let query = &buf[..len];
+            if spoof {
+                let id = u16::from_be_bytes([query[0], query[1]]);
+                let forged = answer(&self::query(id, "other.test"), Ipv4Addr::new(6, 6, 6, 6));
+                socket.send_to(&forged, src).await.unwrap();
+                spoof = false;
+            }
+            socket.send_to(&answer(query, ip), src).await.unwrap();
+        }
+    });
+    addr
+}
+
+/// 应答中第一条记录的 IPv4 地址
+fn answered_ip(response: &[u8]) -> Ipv4Addr {
+    let rdata = &response[response.len() - 4..];
+    Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])
+}
+
+#[tokio::test]
+async fn cache_is_per_server() {
+    let dns = dns();
+    let honest = fake_server(Ipv4Addr::new(192, 0, 2, 1), false).await;
+    let attacker = fake_server(Ipv4Addr::new(203, 0, 113, 66), false).await;
+
+    // 两个关联向各自指定的服务器查询同一个名字
+    let first = dns
+        .handle(&query(1, "shared.test"), attacker)
+        .await
+        .unwrap();
+    assert_eq!(answered_ip(&first), Ipv4Addr::new(203, 0, 113, 66));
+    let second = dns.handle(&query(2, "shared.test"), honest).await.unwrap();
+    assert_eq!(answered_ip(&second), Ipv4Addr::new(192, 0, 2, 1));
+
+    // 同一服务器的重复查询命中缓存，ID 换成新查询的
+    let cached = dns.handle(&query(3, "shared.test"), honest).await.unwrap();
+    assert_eq!(&cached[..2], &3u16.to_be_bytes());
+    assert_eq!(answered_ip(&cached), Ipv4Addr::new(192, 0, 2, 1));
+}
+
+#[tokio::test]
+async fn mismatched_question_dropped() {
+    let dns = dns();
+    let server = fake_server(Ipv4Addr::new(192, 0, 2, 7), true).await;
+
+    let response = dns.handle(&query(9, "real.test"), server).await.unwrap();
+    assert_eq!(answered_ip(&response), Ipv4Addr::new(192, 0, 2, 7));
+
+    // 伪造的应答没有进入 other.test 的缓存
+    let other = dns.handle(&query(10, "other.test"), server).await.unwrap();
+    assert_eq!(answered_ip(&other), Ipv4Addr::new(192, 0, 2, 7));
+}
+
+#[tokio::test]
+async fn inflight_queries_bounded() {
+    // 不应答的上游：每个查询都在途直到超时
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let config = DnsConfig {
+        intercept: true,
+        upstream: Some(silent.local_addr().unwrap().to_string()),
+        ..DnsConfig::default()
+    };
+    let server = Server::builder().dns(config).build().await.unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await.unwrap() });
+
+    let association = Client::new(addr.to_string()).udp_associate().await.unwrap();
+    let dropped = || {
+        udp_drops()
+            .find(|(reason, _)| *reason == UdpDrop::DnsQueue)
+            .map_or(0, |(_, count)| count)
+    };
+    let before = dropped();
+    for id in 0..40 {
+        let query = query(id, &format!("flood{}.test", id));
+        association
+            .send_to(&query, Address::IpV4(Ipv4Addr::LOCALHOST), 53)
+            .await
+            .unwrap();
+    }
+
+    // 超出上限的查询被丢弃，而不是各自占用一个任务
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while dropped() - before < 24 {
+        assert!(Instant::now() < deadline, "dns queries not bounded");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
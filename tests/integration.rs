@@ -0,0 +1,422 @@
+//! 端到端测试：服务端跑在临时端口上，目标是进程内的 TCP/UDP 回显服务
+//!
+//! 回显数据的用例大多通过 `Server::process` 跑在内存 duplex 上 (用户态拷贝路径)，
+//! 协商、认证、应答码和 UDP 关联都经过真实的监听端口；
+//! 另有经监听端口分别开启、关闭 splice 的转发用例 (不支持 splice 的环境会回退到拷贝)
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+use proxy::client::{self, Client};
+use proxy::config::{DnsConfig, TimeoutConfig};
+use proxy::consts::*;
+use proxy::{Address, Server, ServerBuilder, SocksError, SocksRequest};
+
+const WAIT: Duration = Duration::from_secs(10);
+
+/// 在临时端口上启动服务端，返回监听地址
+async fn start(builder: ServerBuilder) -> SocketAddr {
+    let server = builder.build().await.expect("build server");
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await.unwrap() });
+    addr
+}
+
+/// TCP 回显服务
+async fn tcp_echo(ip: IpAddr) -> SocketAddr {
+    let listener = TcpListener::bind((ip, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = socket.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+/// UDP 回显服务
+async fn udp_echo() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+        loop {
+            let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+            socket.send_to(&buf[..len], src).await.unwrap();
+        }
+    });
+    addr
+}
+
+/// 一个没有进程监听的本地端口
+async fn closed_port() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap()
+}
+
+/// 经内存 duplex 上的会话 CONNECT 到目标，发送数据并读回回显
+async fn echo_through(server: &Server, address: Address, port: u16, auth: Option<(&str, &str)>) {
+    let (mut stream, socket) = tokio::io::duplex(64 * 1024);
+    let server = server.clone();
+    let session = tokio::spawn(async move {
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 40000));
+        server.process(socket, peer).await
+    });
+
+    client::negotiate(&mut stream, auth).await.unwrap();
+    let request = SocksRequest {
+        cmd: CMD_CONNECT,
+        address,
+        port,
+    };
+    client::request(&mut stream, &request).await.unwrap();
+
+    let payload = b"hello through socks5";
+    stream.write_all(payload).await.unwrap();
+    let mut echoed = vec![0u8; payload.len()];
+    timeout(WAIT, stream.read_exact(&mut echoed))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&echoed, payload);
+
+    drop(stream);
+    timeout(WAIT, session).await.unwrap().unwrap().unwrap();
+}
+
+/// 期望请求以指定应答码失败
+fn assert_reply<T>(result: Result<T, SocksError>, rep: u8) {
+    match result {
+        Err(SocksError::Upstream(_, code)) => assert_eq!(code, rep, "reply code"),
+        Err(e) => panic!("expected reply 0x{:02x}, got error {}", rep, e),
+        Ok(_) => panic!("expected reply 0x{:02x}, got success", rep),
+    }
+}
+
+#[tokio::test]
+async fn no_auth_negotiation() {
+    let addr = start(Server::builder()).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(&[SOCKS_VERSION, 2, METHOD_PASSWORD, METHOD_NO_AUTH])
+        .await
+        .unwrap();
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, [SOCKS_VERSION, METHOD_NO_AUTH]);
+}
+
+#[tokio::test]
+async fn password_negotiation() {
+    let addr = start(Server::builder().user("alice", "secret")).await;
+    let echo = tcp_echo(Ipv4Addr::LOCALHOST.into()).await;
+
+    let client = Client::new(addr.to_string()).auth("alice", "secret");
+    let (_stream, _) = client
+        .connect(Address::from(echo.ip()), echo.port())
+        .await
+        .unwrap();
+
+    // 服务端要求认证时不接受 NO AUTH
+    let result = Client::new(addr.to_string())
+        .connect(Address::from(echo.ip()), echo.port())
+        .await;
+    assert!(matches!(result, Err(SocksError::NoAcceptableMethod)));
+}
+
+#[tokio::test]
+async fn wrong_credentials() {
+    let addr = start(Server::builder().user("alice", "secret")).await;
+    let echo = tcp_echo(Ipv4Addr::LOCALHOST.into()).await;
+
+    for (user, pass) in [("alice", "wrong"), ("mallory", "secret"), ("", "")] {
+        let result = Client::new(addr.to_string())
+            .auth(user, pass)
+            .connect(Address::from(echo.ip()), echo.port())
+            .await;
+        assert!(
+            matches!(result, Err(SocksError::AuthFailed(_))),
+            "{}:{} accepted",
+            user,
+            pass
+        );
+    }
+}
+
+#[tokio::test]
+async fn connect_reply_over_listener() {
+    let addr = start(Server::builder()).await;
+    let echo = tcp_echo(Ipv4Addr::LOCALHOST.into()).await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    client::negotiate(&mut stream, None).await.unwrap();
+    let request = SocksRequest {
+        cmd: CMD_CONNECT,
+        address: Address::from(echo.ip()),
+        port: echo.port(),
+    };
+    client::request(&mut stream, &request).await.unwrap();
+}
+
+#[tokio::test]
+async fn connect_ipv4() {
+    let server = Server::builder().build().await.unwrap();
+    let echo = tcp_echo(Ipv4Addr::LOCALHOST.into()).await;
+    echo_through(&server, Address::from(echo.ip()), echo.port(), None).await;
+}
+
+#[tokio::test]
+async fn connect_ipv6() {
+    let server = Server::builder().build().await.unwrap();
+    let echo = tcp_echo(Ipv6Addr::LOCALHOST.into()).await;
+    echo_through(&server, Address::from(echo.ip()), echo.port(), None).await;
+}
+
+#[tokio::test]
+async fn connect_domain() {
+    let server = Server::builder()
+        .user("alice", "secret")
+        .build()
+        .await
+        .unwrap();
+    let echo = tcp_echo(Ipv4Addr::LOCALHOST.into()).await;
+    echo_through(
+        &server,
+        Address::Domain("localhost".into()),
+        echo.port(),
+        Some(("alice", "secret")),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn connect_refused() {
+    let addr = start(Server::builder()).await;
+    let target = closed_port().await;
+
+    let result = Client::new(addr.to_string())
+        .connect(Address::from(target.ip()), target.port())
+        .await;
+    assert_reply(result, REP_CONNECTION_REFUSED);
+}
+
+#[tokio::test]
+async fn resolve_timeout() {
+    // 不应答的 DNS 上游：解析一直挂起，直到服务端的连接超时 (1s) 触发
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let dns = DnsConfig {
+        upstream: Some(silent.local_addr().unwrap().to_string()),
+        ..DnsConfig::default()
+    };
+    let addr = start(Server::builder().timeout(1).dns(dns)).await;
+
+    let result = timeout(
+        WAIT,
+        Client::new(addr.to_string()).connect(Address::Domain("slow.test".into()), 80),
+    )
+    .await
+    .unwrap();
+    assert_reply(result, REP_TTL_EXPIRED);
+}
+
+#[tokio::test]
+async fn unsupported_command() {
+    let addr = start(Server::builder()).await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    client::negotiate(&mut stream, None).await.unwrap();
+    let request = SocksRequest {
+        cmd: 0x7f,
+        address: Address::from(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        port: 80,
+    };
+    assert_reply(
+        client::request(&mut stream, &request).await,
+        REP_COMMAND_NOT_SUPPORTED,
+    );
+}
+
+/// 服务端在 `within` 内关闭连接 (读到 EOF)
+async fn assert_closed(stream: &mut TcpStream, within: Duration) {
+    let mut buf = [0u8; 16];
+    loop {
+        let n = timeout(within, stream.read(&mut buf))
+            .await
+            .expect("server kept the connection open")
+            .unwrap_or(0);
+        if n == 0 {
+            return;
+        }
+    }
+}
+
+fn timeouts(greeting: u64, auth: u64, request: u64, idle: u64) -> TimeoutConfig {
+    TimeoutConfig {
+        greeting,
+        auth,
+        request,
+        idle,
+    }
+}
+
+#[tokio::test]
+async fn greeting_timeout() {
+    let addr = start(Server::builder().timeouts(timeouts(1, 10, 10, 0))).await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    assert_closed(&mut stream, Duration::from_secs(3)).await;
+}
+
+#[tokio::test]
+async fn auth_timeout() {
+    let builder = Server::builder()
+        .user("alice", "secret")
+        .timeouts(timeouts(10, 1, 10, 0));
+    let addr = start(builder).await;
+
+    // 协商完成后只发出一半的认证报文
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(&[SOCKS_VERSION, 1, METHOD_PASSWORD])
+        .await
+        .unwrap();
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, [SOCKS_VERSION, METHOD_PASSWORD]);
+    stream.write_all(&[AUTH_VERSION, 5, b'a']).await.unwrap();
+
+    assert_closed(&mut stream, Duration::from_secs(3)).await;
+}
+
+#[tokio::test]
+async fn request_timeout() {
+    let addr = start(Server::builder().timeouts(timeouts(10, 10, 1, 0))).await;
+
+    // 协商完成后迟迟不发请求
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    client::negotiate(&mut stream, None).await.unwrap();
+
+    assert_closed(&mut stream, Duration::from_secs(3)).await;
+}
+
+#[tokio::test]
+async fn relay_idle_timeout() {
+    let server = Server::builder()
+        .timeouts(timeouts(10, 10, 10, 1))
+        .build()
+        .await
+        .unwrap();
+    let echo = tcp_echo(Ipv4Addr::LOCALHOST.into()).await;
+
+    let (mut stream, socket) = tokio::io::duplex(64 * 1024);
+    let session = tokio::spawn(async move {
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 40000));
+        server.process(socket, peer).await
+    });
+    client::negotiate(&mut stream, None).await.unwrap();
+    let request = SocksRequest {
+        cmd: CMD_CONNECT,
+        address: Address::from(echo.ip()),
+        port: echo.port(),
+    };
+    client::request(&mut stream, &request).await.unwrap();
+
+    // 有数据往来时不会被关闭
+    let mut buf = [0u8; 4];
+    for _ in 0..4 {
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        stream.write_all(b"ping").await.unwrap();
+        stream.read_exact(&mut buf).await.unwrap();
+    }
+
+    // 静默超过 1s 后会话结束
+    timeout(Duration::from_secs(3), session)
+        .await
+        .expect("idle session kept open")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn udp_associate_round_trip() {
+    let addr = start(Server::builder().user("alice", "secret")).await;
+    let echo = udp_echo().await;
+
+    let association = Client::new(addr.to_string())
+        .auth("alice", "secret")
+        .udp_associate()
+        .await
+        .unwrap();
+    assert_eq!(association.relay().ip(), addr.ip());
+
+    let mut buf = [0u8; 2048];
+    for i in 0..3 {
+        let payload = format!("datagram {}", i);
+        association
+            .send_to(payload.as_bytes(), Address::from(echo.ip()), echo.port())
+            .await
+            .unwrap();
+        let (len, (source, port)) = timeout(WAIT, association.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..len], payload.as_bytes());
+        assert_eq!(source.to_socket_addr(port), Some(echo));
+    }
+}
+
+#[test]
+fn tunnel_name_ignores_case() {
+    use proxy::reverse::tunnel_name;
+
+    assert_eq!(tunnel_name("office.tunnel"), Some("office"));
+    assert_eq!(tunnel_name("Office.TUNNEL"), Some("Office"));
+    assert_eq!(tunnel_name("office.Tunnel."), Some("office"));
+    assert_eq!(tunnel_name(".tunnel"), None);
+    assert_eq!(tunnel_name("office.tunnels"), None);
+    assert_eq!(tunnel_name("ü.tunnel"), Some("ü"));
+}
+
+/// 经真实监听端口 CONNECT 到回显服务，来回传一段数据
+async fn echo_over_listener(splice: bool) {
+    let addr = start(Server::builder().splice(splice)).await;
+    let echo = tcp_echo(Ipv4Addr::LOCALHOST.into()).await;
+
+    let (mut stream, _) = Client::new(addr.to_string())
+        .connect(Address::from(echo.ip()), echo.port())
+        .await
+        .unwrap();
+    let payload: Vec<u8> = (0..256 * 1024).map(|i| i as u8).collect();
+    let (mut reader, mut writer) = stream.split();
+    let write = async {
+        writer.write_all(&payload).await.unwrap();
+        writer.shutdown().await.unwrap();
+    };
+    let mut echoed = Vec::new();
+    let read = reader.read_to_end(&mut echoed);
+    timeout(WAIT, async { tokio::join!(write, read).1 })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(echoed, payload);
+}
+
+#[tokio::test]
+async fn relay_over_listener_splice() {
+    echo_over_listener(true).await;
+}
+
+#[tokio::test]
+async fn relay_over_listener_copy() {
+    echo_over_listener(false).await;
+}
@@ -0,0 +1,174 @@
+//! 多路复用会话：流量控制、半关闭、RST，以及对违反协议的对端的处理
+
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+use tokio::time::timeout;
+
+use proxy::mux::{Incoming, MuxStream, Session};
+
+const OPEN: u8 = 0x00;
+const DATA: u8 = 0x01;
+const RST: u8 = 0x04;
+const WINDOW: u32 = 1024 * 1024;
+const CHUNK: usize = 16 * 1024;
+const KEEPALIVE: Duration = Duration::from_secs(60);
+const WAIT: Duration = Duration::from_secs(5);
+
+/// 两个直连的会话：本地节点 (奇数 ID) 和远端节点
+fn pair() -> ((Session, Incoming), (Session, Incoming)) {
+    let (a, b) = duplex(4 * WINDOW as usize);
+    (
+        Session::new(a, true, KEEPALIVE),
+        Session::new(b, false, KEEPALIVE),
+    )
+}
+
+/// 远端节点会话，另一头由测试直接读写原始帧
+fn raw_peer() -> (Session, Incoming, DuplexStream) {
+    let (a, b) = duplex(4 * WINDOW as usize);
+    let (session, incoming) = Session::new(b, false, KEEPALIVE);
+    (session, incoming, a)
+}
+
+async fn send_frame(io: &mut DuplexStream, kind: u8, id: u32, data: &[u8]) {
+    let mut frame = vec![kind];
+    frame.extend_from_slice(&id.to_be_bytes());
+    frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    frame.extend_from_slice(data);
+    io.write_all(&frame).await.unwrap();
+}
+
+/// 读到指定类型的帧为止，返回其流 ID；其余帧连同数据一起跳过
+async fn expect_frame(io: &mut DuplexStream, kind: u8) -> u32 {
+    loop {
+        let mut header = [0u8; 9];
+        io.read_exact(&mut header).await.unwrap();
+        let id = u32::from_be_bytes(header[1..5].try_into().unwrap());
+        let length = u32::from_be_bytes(header[5..9].try_into().unwrap());
+        if header[0] == DATA {
+            let mut data = vec![0u8; length as usize];
+            io.read_exact(&mut data).await.unwrap();
+        }
+        if header[0] == kind {
+            return id;
+        }
+    }
+}
+
+async fn accept(incoming: &mut Incoming) -> MuxStream {
+    timeout(WAIT, incoming.accept()).await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn writer_waits_for_window() {
+    let ((local, _), (_, mut incoming)) = pair();
+    let mut stream = local.open().unwrap();
+    stream.write_all(&vec![1u8; WINDOW as usize]).await.unwrap();
+
+    // 窗口用完，对端读取之前再写会阻塞
+    let blocked = timeout(Duration::from_millis(200), stream.write_all(b"x")).await;
+    assert!(blocked.is_err());
+
+    let mut remote = accept(&mut incoming).await;
+    let mut buf = vec![0u8; WINDOW as usize / 2];
+    remote.read_exact(&mut buf).await.unwrap();
+    timeout(WAIT, stream.write_all(b"x"))
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn window_overrun_resets_stream() {
+    let (_session, mut incoming, mut peer) = raw_peer();
+    send_frame(&mut peer, OPEN, 1, &[]).await;
+    let chunk = vec![7u8; CHUNK];
+    for _ in 0..WINDOW as usize / CHUNK {
+        send_frame(&mut peer, DATA, 1, &chunk).await;
+    }
+    // 超出窗口的一帧让会话重置该流
+    send_frame(&mut peer, DATA, 1, &chunk).await;
+    assert_eq!(
+        timeout(WAIT, expect_frame(&mut peer, RST)).await.unwrap(),
+        1
+    );
+
+    // 窗口内的数据照常读出，之后是重置错误
+    let mut stream = accept(&mut incoming).await;
+    let mut buf = vec![0u8; WINDOW as usize];
+    stream.read_exact(&mut buf).await.unwrap();
+    let err = stream.read(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+}
+
+#[tokio::test]
+async fn half_close_keeps_other_direction() {
+    let ((local, _), (_, mut incoming)) = pair();
+    let mut stream = local.open().unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mut remote = accept(&mut incoming).await;
+    let mut received = Vec::new();
+    timeout(WAIT, remote.read_to_end(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received, b"ping");
+
+    // 对端收到 FIN 后仍可继续发送
+    remote.write_all(b"pong").await.unwrap();
+    remote.shutdown().await.unwrap();
+    let mut reply = Vec::new();
+    timeout(WAIT, stream.read_to_end(&mut reply))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply, b"pong");
+}
+
+#[tokio::test]
+async fn drop_sends_rst() {
+    let ((local, _), (_, mut incoming)) = pair();
+    let stream = local.open().unwrap();
+    let mut remote = accept(&mut incoming).await;
+    drop(stream);
+
+    let mut buf = [0u8; 16];
+    let err = timeout(WAIT, remote.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+    let err = remote.write_all(b"late").await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+}
+
+#[tokio::test]
+async fn duplicate_open_closes_session() {
+    let (session, mut incoming, mut peer) = raw_peer();
+    send_frame(&mut peer, OPEN, 1, &[]).await;
+    let mut first = accept(&mut incoming).await;
+
+    // 重复的 OPEN 不会替换已有的流，而是作为协议错误结束会话
+    send_frame(&mut peer, OPEN, 1, &[]).await;
+    let mut buf = [0u8; 16];
+    let err = timeout(WAIT, first.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+    assert!(session.is_closed());
+    assert!(incoming.accept().await.is_none());
+}
+
+#[tokio::test]
+async fn open_with_own_id_closes_session() {
+    let (session, mut incoming, mut peer) = raw_peer();
+    // 偶数 ID 属于远端节点自己的 ID 空间
+    send_frame(&mut peer, OPEN, 2, &[]).await;
+    assert!(timeout(WAIT, incoming.accept()).await.unwrap().is_none());
+    assert!(session.is_closed());
+}
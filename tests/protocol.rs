@@ -0,0 +1,85 @@
+//! 报文编解码的性质测试：write 之后 parse 得到原值，任意字节不会让解析 panic
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use proptest::prelude::*;
+
+use proxy::{Address, SocksRequest, UDPAssociateHeader};
+
+/// 域名按字节计长度，协议限制为 255 字节；最多 63 个字符 (每个至多 4 字节) 保证不超限
+fn address() -> impl Strategy<Value = Address> {
+    prop_oneof![
+        any::<[u8; 4]>().prop_map(|b| Address::IpV4(Ipv4Addr::from(b))),
+        any::<[u8; 16]>().prop_map(|b| Address::IpV6(Ipv6Addr::from(b))),
+        "\\PC{0,63}".prop_map(Address::Domain),
+    ]
+}
+
+fn header() -> impl Strategy<Value = UDPAssociateHeader> {
+    (any::<u8>(), address(), any::<u16>()).prop_map(|(frag, address, port)| UDPAssociateHeader {
+        frag,
+        address,
+        port,
+    })
+}
+
+fn request() -> impl Strategy<Value = SocksRequest> {
+    (any::<u8>(), address(), any::<u16>()).prop_map(|(cmd, address, port)| SocksRequest {
+        cmd,
+        address,
+        port,
+    })
+}
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+proptest! {
+    #[test]
+    fn udp_header_round_trip(header in header(), payload in prop::collection::vec(any::<u8>(), 0..64)) {
+        let mut packet = Vec::new();
+        header.write(&mut packet);
+        let header_len = packet.len();
+        packet.extend_from_slice(&payload);
+
+        let (parsed, consumed) = UDPAssociateHeader::parse(&packet).unwrap();
+        prop_assert_eq!(parsed, header);
+        prop_assert_eq!(consumed, header_len);
+        prop_assert_eq!(&packet[consumed..], &payload[..]);
+    }
+
+    /// 保留字段合法、ATYP 偏向已知类型，让随机字节能走到地址解析
+    #[test]
+    fn udp_header_parse_arbitrary(
+        frag in any::<u8>(),
+        atyp in prop_oneof![Just(1u8), Just(3u8), Just(4u8), any::<u8>()],
+        tail in prop::collection::vec(any::<u8>(), 0..32),
+    ) {
+        let mut packet = vec![0x00, 0x00, frag, atyp];
+        packet.extend_from_slice(&tail);
+        if let Ok((_, consumed)) = UDPAssociateHeader::parse(&packet) {
+            prop_assert!(consumed <= packet.len());
+        }
+    }
+
+    #[test]
+    fn request_round_trip(request in request()) {
+        let mut buf = Vec::new();
+        request.write(&mut buf);
+
+        let mut reader = &buf[..];
+        let parsed = block_on(SocksRequest::read_from(&mut reader)).unwrap();
+        prop_assert_eq!(parsed, request);
+        prop_assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn request_parse_arbitrary(buf in prop::collection::vec(any::<u8>(), 0..300)) {
+        let mut reader = &buf[..];
+        let _ = block_on(SocksRequest::read_from(&mut reader));
+    }
+}
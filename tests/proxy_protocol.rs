@@ -0,0 +1,126 @@
+//! PROXY protocol 头部解析：v1/v2 的各种命令与地址族，以及头部之后的数据不被多读
+
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+
+use proxy::proxy_protocol::{header, read_header};
+
+/// 紧跟在头部后面的 SOCKS5 方法协商
+const GREETING: &[u8] = &[0x05, 0x01, 0x00];
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// 解析 `input`，返回结果和剩余未读的字节
+async fn parse(input: &[u8]) -> (std::io::Result<Option<SocketAddr>>, Vec<u8>) {
+    let mut reader = input;
+    let result = read_header(&mut reader).await;
+    (result, reader.to_vec())
+}
+
+fn with_greeting(header: &[u8]) -> Vec<u8> {
+    [header, GREETING].concat()
+}
+
+/// v2 头部：`cmd` 为低 4 位命令，`family` 为地址族与传输层
+fn v2(cmd: u8, family: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = V2_SIGNATURE.to_vec();
+    buf.push(0x20 | cmd);
+    buf.push(family);
+    buf.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    buf.extend_from_slice(body);
+    buf
+}
+
+#[tokio::test]
+async fn v1_tcp4() {
+    let input = with_greeting(b"PROXY TCP4 192.0.2.1 198.51.100.2 40000 1080\r\n");
+    let (result, rest) = parse(&input).await;
+    assert_eq!(result.unwrap(), Some("192.0.2.1:40000".parse().unwrap()));
+    assert_eq!(rest, GREETING);
+}
+
+#[tokio::test]
+async fn v1_tcp6() {
+    let input = with_greeting(b"PROXY TCP6 2001:db8::1 2001:db8::2 40000 1080\r\n");
+    let (result, rest) = parse(&input).await;
+    assert_eq!(
+        result.unwrap(),
+        Some("[2001:db8::1]:40000".parse().unwrap())
+    );
+    assert_eq!(rest, GREETING);
+}
+
+#[tokio::test]
+async fn v1_unknown() {
+    let input = with_greeting(b"PROXY UNKNOWN\r\n");
+    let (result, rest) = parse(&input).await;
+    assert_eq!(result.unwrap(), None);
+    assert_eq!(rest, GREETING);
+}
+
+#[tokio::test]
+async fn v1_too_long() {
+    let mut line = b"PROXY TCP4 ".to_vec();
+    line.resize(200, b'1');
+    line.extend_from_slice(b"\r\n");
+    let (result, _) = parse(&line).await;
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn v2_proxy_with_tlv() {
+    let source: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+    let dest: SocketAddr = "198.51.100.2:1080".parse().unwrap();
+    let input = with_greeting(&header(2, source, dest, Some("alice")));
+    let (result, rest) = parse(&input).await;
+    assert_eq!(result.unwrap(), Some(source));
+    assert_eq!(rest, GREETING);
+}
+
+#[tokio::test]
+async fn v2_proxy_ipv6() {
+    let source: SocketAddr = "[2001:db8::1]:40000".parse().unwrap();
+    let dest: SocketAddr = "[2001:db8::2]:1080".parse().unwrap();
+    let input = with_greeting(&header(2, source, dest, None));
+    let (result, rest) = parse(&input).await;
+    assert_eq!(result.unwrap(), Some(source));
+    assert_eq!(rest, GREETING);
+}
+
+#[tokio::test]
+async fn v2_local() {
+    // LOCAL 的地址块照样被消费掉
+    let input = with_greeting(&v2(0x00, 0x11, &[0u8; 12]));
+    let (result, rest) = parse(&input).await;
+    assert_eq!(result.unwrap(), None);
+    assert_eq!(rest, GREETING);
+}
+
+#[tokio::test]
+async fn v2_unspec_family() {
+    let input = with_greeting(&v2(0x01, 0x00, &[]));
+    let (result, rest) = parse(&input).await;
+    assert_eq!(result.unwrap(), None);
+    assert_eq!(rest, GREETING);
+}
+
+#[tokio::test]
+async fn v2_truncated_address() {
+    // 地址族声明为 IPv4/IPv6，长度却放不下两个地址和端口
+    for (family, len) in [(0x11, 11), (0x21, 35)] {
+        let input = with_greeting(&v2(0x01, family, &vec![0u8; len]));
+        let (result, _) = parse(&input).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    // 声明的长度超过实际数据
+    let mut input = v2(0x01, 0x11, &[0u8; 12]);
+    input.truncate(input.len() - 4);
+    let (result, _) = parse(&input).await;
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn missing_header() {
+    let (result, _) = parse(&with_greeting(b"GET / HTTP/1.1\r\n")).await;
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+}